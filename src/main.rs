@@ -5,10 +5,31 @@ use math::vector::Point3D;
 mod simulation;
 use simulation::ray::Ray;
 use simulation::engine::Engine;
+use simulation::bdpt;
+use simulation::photon::PhotonMapping;
 use simulation::hittable::HittableList;
 use simulation::hittable::Sphere;
+use simulation::engine::{PixelContext, Progressive, Region};
+use simulation::material::{Conductor, Dielectric, Lambertian};
+use simulation::principled::Principled;
+use simulation::spectrum::{self, Ior};
+use simulation::filter::Filter;
+use simulation::camera::{CameraModel, View, Equirectangular, Fisheye, Orthographic, Perspective};
+use simulation::aperture::{Aperture, ApertureImage};
+use simulation::lens::{Lens, RealisticCamera};
+use simulation::stereo::{CubeMap, Layout, OmniStereo, Stereo};
+use simulation::adaptive::AdaptiveSampling;
+use simulation::mlt::Mlt;
+use simulation::denoise::Denoiser;
+use simulation::exposure::{CameraSettings, Exposure};
+use simulation::post::{Bloom, ChromaticAberration, FilmGrain, Glare, PostStack, Vignette};
+use simulation::firefly::{OutlierRejection, SampleClamp};
+use simulation::checkpoint::Checkpoint;
+use simulation::progress::Progress;
+use simulation::aov::{Aov, AovOutput};
 
 
+use crate::math::color::ColorSpace;
 use crate::math::vector::Color;
 use crate::simulation::hittable::Hittable;
 
@@ -16,35 +37,360 @@ use crate::simulation::hittable::Hittable;
 const IMAGE_WIDTH : i32 = 256;
 
 
-fn pixel_main(_ray: &Ray, _world: &HittableList, _x: i32, _y: i32, _u: f32, _v: f32, trace: i32) -> Color {
-    let t = _world.hit(_ray, 0., 1000.0);
+/// Adds light reaching the camera after `bounce` bounces, split into the lighting AOVs.
+fn contribute(ctx: &mut PixelContext, bounce: i32, light: Color, radiance: &mut Color) {
+    let light = ctx.clamp_light(bounce, light);
+    *radiance += light;
 
-    if (trace <= 0) {
-        return Color::new(0., 0., 0.);
+    let aov = match bounce {
+        0 => Aov::Emission,
+        1 => Aov::Direct,
+        _ => Aov::Indirect
+    };
+    ctx.aov.add(aov, light);
+}
+
+/// Gradient sky lighting the scene, scaled by `brightness`.
+fn sky(ray: &Ray, brightness: f32) -> Color {
+    let unit = ray.direction().normalized();
+    let t = 0.5 * (unit.y + 1.0);
+
+    spectrum::at_wavelength(Vector3::lerp(Vector3{x: 1., y: 1., z: 1.}, Vector3{x: 0.5, y: 0.7, z: 1.}, t), ray.wavelength()) * brightness
+}
+
+fn pixel_main(_ray: &Ray, _world: &HittableList, ctx: &mut PixelContext, trace: i32, sky_brightness: f32) -> Color {
+    let mut radiance = Color::zero();
+    let mut throughput = Color::new(1., 1., 1.);
+    let mut ray = _ray.spawn(&_ray.origin(), &_ray.direction());
+
+    for bounce in 0..trace {
+        ctx.rays += 1;
+
+        let Some(v) = _world.hit(&ray, 0.001, 1000.0) else {
+            contribute(ctx, bounce, throughput * sky(&ray, sky_brightness), &mut radiance);
+            break;
+        };
+
+        if bounce == 0 {
+            ctx.aov.set_scalar(Aov::Depth, v.t());
+            ctx.aov.set(Aov::Normal, v.normal());
+            ctx.aov.set(Aov::Position, v.p());
+            ctx.aov.set_scalar(Aov::ObjectId, v.object_id() as f32);
+            ctx.aov.set_scalar(Aov::MaterialId, v.material_id() as f32);
+        }
+
+        let Some(material) = v.material() else {
+            let target: Point3D = v.p() + v.normal();
+            throughput *= 0.5;
+            ray = ray.spawn(&v.p(), &(target - v.p()));
+            continue;
+        };
+
+        if bounce == 0 {
+            ctx.aov.set(Aov::Albedo, material.albedo(&v));
+        }
+
+        contribute(ctx, bounce, throughput * material.emitted(&v), &mut radiance);
+
+        let bsdf = material.bsdf(&v);
+        let wo = -ray.direction().normalized();
+
+        let uc = ctx.sampler.next_1d();
+        let u = ctx.sampler.next_2d();
+
+        let Some(s) = bsdf.sample_f(wo, uc, u) else {
+            break;
+        };
+
+        throughput = throughput * s.f * (s.wi.dot(v.normal()).abs() / s.pdf);
+        ray = ray.spawn(&v.p(), &s.wi);
     }
 
-    if let Some(v) = t {
-        let target: Point3D = v.p() + v.normal();
-        return pixel_main(&Ray::new(&v.p(), &(target - v.p())), _world, _x, _y, _u, _v, trace - 1) * 0.5;
-    } else {
-        let unit = _ray.direction().normalized();
-        let t = 0.5 * (unit.y + 1.0);
+    radiance
+}
 
-        return Vector3::lerp(Vector3{x: 1., y: 1., z: 1.}, Vector3{x: 0.5, y: 0.7, z: 1.}, t)
+fn format_duration(d: std::time::Duration) -> String {
+    let s = d.as_secs();
+    format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
+
+fn print_progress(p: &Progress) {
+    const WIDTH: usize = 30;
+
+    let fraction = p.fraction();
+    let filled = (fraction * WIDTH as f64) as usize;
+    let eta = p.eta.map_or(String::from("--:--:--"), format_duration);
+    let pass = if p.passes > 0 { format!("{}/{}", p.pass, p.passes) } else { p.pass.to_string() };
+
+    eprint!(
+        "\r[{}{}] {:5.1}%  pass {}  tile {}/{}  {:.2} Msamples/s  {:.2} Mrays/s  elapsed {}  ETA {}   ",
+        "#".repeat(filled),
+        ".".repeat(WIDTH - filled),
+        fraction * 100.,
+        pass,
+        p.tiles_done,
+        p.tiles_total,
+        p.samples_per_sec / 1e6,
+        p.rays_per_sec / 1e6,
+        format_duration(p.elapsed),
+        eta
+    );
+}
+
+/// Renders the color of a camera ray, given the world and the maximum number of rays per path.
+type Integrator = Box<dyn Fn(&Ray, &HittableList, &mut PixelContext, i32) -> Color + Sync>;
+
+/// Sky brightness when the scene is lit by the lamp.
+const NIGHT_SKY: f32 = 0.02;
+
+/// Interocular distance and zero parallax distance for stereo renders, in scene units.
+const STEREO_IPD: f32 = 0.064;
+const STEREO_CONVERGENCE: f32 = 1.5;
+
+fn set_camera<C: CameraModel + 'static, F: Fn(&Ray, &HittableList, &mut PixelContext, i32) -> Color + Sync>(engine: &mut Engine<F>, camera: C, stereo: Option<Layout>) {
+    match stereo {
+        Some(layout) => engine.set_camera(Stereo::new(camera, STEREO_IPD, STEREO_CONVERGENCE).with_layout(layout)),
+        None => engine.set_camera(camera)
     }
 }
 
 fn main() {
-    let engine= &mut Engine::new("output.ppm", IMAGE_WIDTH, 16. / 9., pixel_main, 500, 31);
+    let camera = std::env::args().find_map(|a| a.strip_prefix("--camera=").map(String::from)).unwrap_or(String::from("perspective"));
+    let layout = if std::env::args().any(|a| a == "--over-under") { Layout::OverUnder } else { Layout::SideBySide };
+    let stereo = std::env::args().any(|a| a == "--stereo").then_some(layout);
+
+    // aspect ratio of one view, and the film holding all of them
+    let view_aspect = match camera.as_str() {
+        "equirectangular" | "ods" => 2.,
+        "cubemap" => 1.,
+        _ => 16. / 9.
+    };
+    let views = match camera.as_str() {
+        "cubemap" => 6,
+        "ods" => 2,
+        _ if stereo.is_some() => 2,
+        _ => 1
+    };
+
+    let lamp = std::env::args().any(|a| a == "--lamp");
+    let sky_brightness = if lamp { NIGHT_SKY } else { 1. };
+
+    let name = std::env::args().find_map(|a| a.strip_prefix("--integrator=").map(String::from)).unwrap_or(String::from("path"));
+    let photons = std::env::args().find_map(|a| a.strip_prefix("--photons=").and_then(|n| n.parse().ok())).unwrap_or(200_000);
+    let photon_mapping = std::sync::Arc::new(PhotonMapping::new(photons));
+    let integrator: Integrator = match name.as_str() {
+        "bdpt" => Box::new(move |ray, world, ctx, trace| bdpt::radiance(ray, world, ctx, trace, &|r| sky(r, sky_brightness))),
+        "photon" => {
+            let photon_mapping = photon_mapping.clone();
+            Box::new(move |ray, world, ctx, trace| photon_mapping.radiance(ray, world, ctx, trace, &|r| sky(r, sky_brightness)))
+        }
+        other => {
+            if other != "path" {
+                eprintln!("[ERROR] Unknown integrator {}, using path tracing.", other);
+            }
+            Box::new(move |ray, world, ctx, trace| pixel_main(ray, world, ctx, trace, sky_brightness))
+        }
+    };
+
+    let engine= &mut Engine::new("output.ppm", IMAGE_WIDTH, layout.aspect_ratio(view_aspect, views), integrator, 500, 31);
+
+    engine.world().add(Sphere::new(Point3D::new(0., 0., -1.), 0.5).with_material(Principled::new(Color::new(0.7, 0.3, 0.3)).with_roughness(0.4).with_clearcoat(1.)));
+    engine.world().add(Sphere::new(Point3D::new(-1., 0., -1.), 0.5).with_material(Dielectric::dispersive(Ior::bk7(), 0.05)));
+    engine.world().add(Sphere::new(Point3D::new(1., 0., -1.), 0.5).with_material(Conductor::gold(0.3)));
+    engine.world().add(Sphere::new(Point3D::new(0.,-100.5,-1.), 100.).with_material(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+    if lamp {
+        // small and bright, above the glass sphere so it casts a caustic
+        engine.world().add(Sphere::new(Point3D::new(-1.3, 1.2, -0.7), 0.1).with_material(Principled::new(Color::zero()).with_emission(Color::new(1., 0.85, 0.6)).with_emission_strength(60.)));
+    }
+
+    engine.set_filter(Filter::mitchell(2.));
 
-    engine.world().add(Sphere::new(Point3D::new(0., 0., -1.), 0.5));
-    engine.world().add(Sphere::new(Point3D::new(0.,-100.5,-1.), 100.));
+    match camera.as_str() {
+        "perspective" => {
+            let mut camera = Perspective::new(view_aspect);
+            if let Some(t) = std::env::args().find_map(|a| a.strip_prefix("--thin-lens=").map(|t| t.split(',').filter_map(|v| v.parse().ok()).collect::<Vec<f32>>()))
+                && let [lens_radius, focus_distance] = t[..]
+            {
+                camera = camera.with_thin_lens(lens_radius, focus_distance);
+            }
+            if let Some(blades) = std::env::args().find_map(|a| a.strip_prefix("--blades=").and_then(|n| n.parse().ok())) {
+                camera = camera.with_aperture(Aperture::polygonal(blades, 0.));
+            }
+            if let Some(file) = std::env::args().find_map(|a| a.strip_prefix("--aperture-image=").map(String::from)) {
+                match ApertureImage::load(&file) {
+                    Ok(image) => camera = camera.with_aperture(Aperture::Image(image)),
+                    Err(e) => eprintln!("[ERROR] Failed to load aperture image: {}", e)
+                }
+            }
+            set_camera(engine, camera, stereo)
+        }
+        "orthographic" => set_camera(engine, Orthographic::new(view_aspect, 2.5), stereo),
+        "fisheye" => set_camera(engine, Fisheye::new(view_aspect, 180.), stereo),
+        "equirectangular" => set_camera(engine, Equirectangular::new(), stereo),
+        "ods" => engine.set_camera(OmniStereo::new(STEREO_IPD).with_layout(layout)),
+        "cubemap" => engine.set_camera(CubeMap::new(layout)),
+        "lens" => {
+            let file = std::env::args().find_map(|a| a.strip_prefix("--lens=").map(String::from)).unwrap_or(String::from("lenses/dgauss.50mm.dat"));
+            // looking at the center sphere from behind the camera origin, focused on it by default
+            let (from, at) = (Point3D::new(0., 0., 4.), Point3D::new(0., 0., -1.));
+            let focus = std::env::args().find_map(|a| a.strip_prefix("--focus=").and_then(|f| f.parse().ok())).unwrap_or((at - from).magnitude());
 
-    eprintln!("[INFO] Simulation started.");
-    engine.simulate();
-    eprintln!("[INFO] Simulation completed.\n");
+            let mut lens = match Lens::load(&file) {
+                Ok(lens) => lens,
+                Err(e) => {
+                    eprintln!("[ERROR] Failed to load lens: {}", e);
+                    return;
+                }
+            };
+            if let Some(aperture) = std::env::args().find_map(|a| a.strip_prefix("--aperture=").and_then(|a| a.parse().ok())) {
+                lens = lens.with_aperture(aperture);
+            }
+
+            // the 35 mm film diagonal
+            match RealisticCamera::new(lens, view_aspect, 43.3, focus) {
+                Ok(camera) => set_camera(engine, camera.with_view(View::look_at(from, at, Vector3::new(0., 1., 0.))), stereo),
+                Err(e) => {
+                    eprintln!("[ERROR] Failed to focus lens: {}", e);
+                    return;
+                }
+            }
+        }
+        other => eprintln!("[ERROR] Unknown camera {}.", other)
+    }
+    let spectral = std::env::args().any(|a| a == "--spectral");
+    if spectral && name == "photon" {
+        eprintln!("[ERROR] Photon mapping traces RGB photons, ignoring --spectral.");
+    }
+    engine.set_spectral(spectral && name != "photon");
+
+    if std::env::args().any(|a| a == "--adaptive") {
+        engine.set_adaptive(Some(AdaptiveSampling::new(32, 1000, 0.02)));
+    }
+    if let Some(pass) = std::env::args().find_map(|a| a.strip_prefix("--progressive=").and_then(|n| n.parse().ok())) {
+        engine.set_progressive(Some(Progressive::new(pass).with_snapshots(std::env::args().any(|a| a == "--snapshots"))));
+    }
+    // mutations per pixel may be given, --mlt=200
+    let mlt = std::env::args().find_map(|a| a.strip_prefix("--mlt").map(|n| n.trim_start_matches('=').parse().unwrap_or(100)));
+    if let Some(mutations) = mlt {
+        engine.set_mlt(Some(Mlt::new(mutations)));
+    }
+    if let Some(file) = std::env::args().find_map(|a| a.strip_prefix("--checkpoint=").map(String::from)) {
+        engine.set_checkpoint(Some(Checkpoint::new(&file).with_resume(std::env::args().any(|a| a == "--resume"))));
+    }
+    engine.set_heatmap(std::env::args().find_map(|a| a.strip_prefix("--heatmap=").map(String::from)).as_deref());
+
+    if let Some(space) = std::env::args().find_map(|a| a.strip_prefix("--working-space=").and_then(ColorSpace::parse)) {
+        engine.set_working_space(space);
+    }
+
+    if let Some(threads) = std::env::args().find_map(|a| a.strip_prefix("--threads=").and_then(|n| n.parse().ok())) {
+        engine.set_threads(threads);
+    }
+    if let Some(seconds) = std::env::args().find_map(|a| a.strip_prefix("--time-limit=").and_then(|n| n.parse().ok())) {
+        engine.set_time_limit(Some(std::time::Duration::from_secs_f64(seconds)));
+    }
+    if let Some(r) = std::env::args().find_map(|a| a.strip_prefix("--region=").map(|r| r.split(',').filter_map(|v| v.parse().ok()).collect::<Vec<i32>>()))
+        && let [x0, y0, x1, y1] = r[..]
+    {
+        engine.set_region(Some(Region::new(x0, y0, x1, y1).with_crop(std::env::args().any(|a| a == "--crop"))));
+    }
+    if let Some(list) = std::env::args().find_map(|a| a.strip_prefix("--aovs=").map(String::from)) {
+        let aovs: Vec<Aov> = if list == "all" { Aov::ALL.to_vec() } else { list.split(',').filter_map(Aov::parse).collect() };
+        let output = if std::env::args().any(|a| a == "--aov-separate") { AovOutput::Separate } else { AovOutput::MultiChannel };
+        engine.set_aovs(&aovs, output);
+    }
+    if let Some(t) = std::env::args().find_map(|a| a.strip_prefix("--clamp=").map(|t| t.split(',').filter_map(|v| v.parse().ok()).collect::<Vec<f32>>()))
+        && let [direct, indirect] = t[..]
+    {
+        engine.set_sample_clamp(Some(SampleClamp::new(direct, indirect)));
+    }
+    if std::env::args().any(|a| a == "--reject-outliers") {
+        engine.set_outlier_rejection(Some(OutlierRejection::default()));
+    }
+    if std::env::args().any(|a| a == "--denoise") {
+        engine.set_denoiser(Some(Denoiser::new()));
+    }
+    // shutter times may be given as fractions, --shutter=1/125
+    let shutter = std::env::args().find_map(|a| a.strip_prefix("--shutter=").and_then(|s| match s.split_once('/') {
+        Some((n, d)) => n.parse::<f32>().ok().zip(d.parse::<f32>().ok()).map(|(n, d)| n / d),
+        None => s.parse().ok()
+    }));
+    let iso = std::env::args().find_map(|a| a.strip_prefix("--iso=").and_then(|n| n.parse().ok()));
+    let f_stop = std::env::args().find_map(|a| a.strip_prefix("--f-stop=").and_then(|n| n.parse().ok()));
+    if let Some(compensation) = std::env::args().find_map(|a| a.strip_prefix("--auto-exposure").map(|c| c.trim_start_matches('=').parse().unwrap_or(0.))) {
+        engine.set_exposure(Some(Exposure::Auto { key: 0.18, compensation }));
+    } else if let Some(stops) = std::env::args().find_map(|a| a.strip_prefix("--exposure=").and_then(|n| n.parse::<f32>().ok())) {
+        engine.set_exposure(Some(Exposure::Manual(stops.exp2())));
+    } else if iso.is_some() || shutter.is_some() || f_stop.is_some() {
+        let settings = CameraSettings::new(iso.unwrap_or(100.), shutter.unwrap_or(1.), f_stop.unwrap_or(1.));
+        eprintln!("[INFO] Exposure ISO {} {}s f/{}, EV100 {:.2}.", settings.iso, settings.shutter, settings.f_stop, settings.ev100());
+        engine.set_exposure(Some(Exposure::Physical(settings)));
+    }
+    if let Some(list) = std::env::args().find_map(|a| a.strip_prefix("--post=").map(String::from)) {
+        let mut post = PostStack::new();
+        for effect in list.split(',') {
+            match effect {
+                "bloom" => post.push(Bloom::default()),
+                "glare" => post.push(Glare::default()),
+                "vignette" => post.push(Vignette::new(0.6)),
+                "aberration" => post.push(ChromaticAberration::new(0.004)),
+                "grain" => post.push(FilmGrain::new(0.08, 1.5)),
+                _ => eprintln!("[ERROR] Unknown post effect {}.", effect)
+            }
+        }
+        engine.set_post(post);
+    }
+    engine.set_progress(print_progress);
+
+    // pressing enter stops the render early, what was sampled so far is still written
+    let cancel = engine.cancel_token();
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).is_ok_and(|n| n > 0) {
+            cancel.cancel();
+        }
+    });
+
+    if name == "photon" {
+        let (trace, threads) = (engine.trace(), engine.threads());
+        photon_mapping.trace(engine.world(), trace, threads);
+        if let Some((caustic, global)) = photon_mapping.stored() {
+            eprintln!("[INFO] Photon maps hold {} caustic and {} global photons.", caustic, global);
+        }
+    }
+
+    eprintln!("[INFO] Simulation started, press enter to stop early.");
+    let result = engine.simulate();
+    eprintln!();
+    if let Err(e) = result {
+        eprintln!("[ERROR] Simulation failed: {}", e);
+        return;
+    }
+    if engine.cancel_token().is_cancelled() {
+        eprintln!("[INFO] Simulation stopped early.");
+    }
+    if mlt.is_some() {
+        eprintln!("[INFO] Simulation completed, {:.1} mutations per pixel.", engine.achieved_spp());
+    } else {
+        eprintln!("[INFO] Simulation completed, {:.1} samples per pixel.", engine.achieved_spp());
+    }
+
+    let fireflies = engine.firefly_stats();
+    if fireflies.clamped_samples > 0 {
+        eprintln!("[INFO] Clamped {} samples ({:.3}%).", fireflies.clamped_samples, fireflies.clamped_fraction() * 100.);
+    }
+    if fireflies.rejected_pixels > 0 {
+        eprintln!("[INFO] Rejected {} outlier pixels.", fireflies.rejected_pixels);
+    }
+    if engine.exposure_multiplier() != 1. {
+        eprintln!("[INFO] Exposure multiplier {:.4} ({:+.2} EV).", engine.exposure_multiplier(), engine.exposure_multiplier().log2());
+    }
+    eprintln!();
 
     eprintln!("[INFO] Render to PPM started.");
-    engine.render();
+    if let Err(e) = engine.render() {
+        eprintln!("[ERROR] Failed to write output.ppm: {}", e);
+        return;
+    }
     eprintln!("[INFO] Render to PPM completed.");
 }
\ No newline at end of file
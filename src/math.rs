@@ -1,4 +1,6 @@
 pub mod array;
 pub mod vector;
 pub mod util;
-pub mod noise;
\ No newline at end of file
+pub mod noise;
+pub mod sampling;
+pub mod color;
\ No newline at end of file
@@ -0,0 +1,198 @@
+use crate::math::vector::{Color, Vector3};
+
+type Mat3 = [[f32; 3]; 3];
+
+/// Color encodings the renderer understands. All RGB spaces use D65 white except ACEScg (D60).
+#[allow(dead_code)]
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ColorSpace {
+    LinearSrgb,
+    Rec2020,
+    AcesCg,
+    CieXyz,
+    /// sRGB primaries with the display transfer function, what 8-bit images are stored in.
+    Srgb,
+    /// Non-color data (normal maps, roughness, ...), never converted.
+    Raw
+}
+
+const SRGB_TO_XYZ: Mat3 = [
+    [0.412_456_4, 0.357_576_1, 0.180_437_5],
+    [0.212_672_9, 0.715_152_2, 0.072_175],
+    [0.019_333_9, 0.119_192, 0.950_304_1]
+];
+
+const XYZ_TO_SRGB: Mat3 = [
+    [3.240_454_2, -1.537_138_5, -0.498_531_4],
+    [-0.969_266, 1.876_010_8, 0.041_556],
+    [0.055_643_4, -0.204_025_9, 1.057_225_2]
+];
+
+const REC2020_TO_XYZ: Mat3 = [
+    [0.636_958, 0.144_616_9, 0.168_881],
+    [0.262_700_2, 0.677_998_1, 0.059_301_7],
+    [0.0, 0.028_072_7, 1.060_985_1]
+];
+
+const XYZ_TO_REC2020: Mat3 = [
+    [1.716_651_2, -0.355_670_8, -0.253_366_3],
+    [-0.666_684_4, 1.616_481_2, 0.015_768_5],
+    [0.017_639_9, -0.042_770_6, 0.942_103_1]
+];
+
+// ACEScg primaries relative to their own D60 white, combined with Bradford adaptation to D65 below.
+const AP1_TO_XYZ_D60: Mat3 = [
+    [0.662_454_2, 0.134_004_2, 0.156_187_7],
+    [0.272_228_7, 0.674_081_8, 0.053_689_5],
+    [-0.005_574_6, 0.004_060_7, 1.010_339_1]
+];
+
+const XYZ_D60_TO_AP1: Mat3 = [
+    [1.641_023_4, -0.324_803_3, -0.236_424_7],
+    [-0.663_662_9, 1.615_331_6, 0.016_756_3],
+    [0.011_721_9, -0.008_284_4, 0.988_394_9]
+];
+
+const BRADFORD_D60_TO_D65: Mat3 = [
+    [0.987_224, -0.006_113_27, 0.015_953_3],
+    [-0.007_598_36, 1.001_86, 0.005_330_02],
+    [0.003_072_57, -0.005_095_95, 1.081_68]
+];
+
+const BRADFORD_D65_TO_D60: Mat3 = [
+    [1.013_03, 0.006_105_31, -0.014_971],
+    [0.007_698_23, 0.998_165, -0.005_032_03],
+    [-0.002_841_31, 0.004_685_16, 0.924_507]
+];
+
+fn mul(m: &Mat3, c: Vector3) -> Vector3 {
+    Vector3::new(
+        m[0][0] * c.x + m[0][1] * c.y + m[0][2] * c.z,
+        m[1][0] * c.x + m[1][1] * c.y + m[1][2] * c.z,
+        m[2][0] * c.x + m[2][1] * c.y + m[2][2] * c.z
+    )
+}
+
+/// sRGB transfer function, display value to linear.
+pub fn srgb_decode(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// sRGB transfer function, linear to display value.
+pub fn srgb_encode(v: f32) -> f32 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    }
+}
+
+#[allow(dead_code)]
+impl ColorSpace {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "linear-srgb" | "srgb-linear" | "linear" => Some(ColorSpace::LinearSrgb),
+            "rec2020" | "rec.2020" => Some(ColorSpace::Rec2020),
+            "acescg" => Some(ColorSpace::AcesCg),
+            "xyz" | "ciexyz" => Some(ColorSpace::CieXyz),
+            "srgb" => Some(ColorSpace::Srgb),
+            "raw" | "non-color" => Some(ColorSpace::Raw),
+            _ => None
+        }
+    }
+
+    /// Linear space with the same primaries, the only kind usable for shading.
+    pub fn linear(self) -> Self {
+        match self {
+            ColorSpace::Srgb | ColorSpace::Raw => ColorSpace::LinearSrgb,
+            s => s
+        }
+    }
+
+    pub fn to_xyz(self, c: Color) -> Vector3 {
+        match self {
+            ColorSpace::LinearSrgb | ColorSpace::Raw => mul(&SRGB_TO_XYZ, c),
+            ColorSpace::Srgb => mul(&SRGB_TO_XYZ, Color::new(srgb_decode(c.x), srgb_decode(c.y), srgb_decode(c.z))),
+            ColorSpace::Rec2020 => mul(&REC2020_TO_XYZ, c),
+            ColorSpace::AcesCg => mul(&BRADFORD_D60_TO_D65, mul(&AP1_TO_XYZ_D60, c)),
+            ColorSpace::CieXyz => c
+        }
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    pub fn from_xyz(self, c: Vector3) -> Color {
+        match self {
+            ColorSpace::LinearSrgb | ColorSpace::Raw => mul(&XYZ_TO_SRGB, c),
+            ColorSpace::Srgb => {
+                let l = mul(&XYZ_TO_SRGB, c);
+                Color::new(srgb_encode(l.x), srgb_encode(l.y), srgb_encode(l.z))
+            }
+            ColorSpace::Rec2020 => mul(&XYZ_TO_REC2020, c),
+            ColorSpace::AcesCg => mul(&XYZ_D60_TO_AP1, mul(&BRADFORD_D65_TO_D60, c)),
+            ColorSpace::CieXyz => c
+        }
+    }
+
+    pub fn convert(c: Color, from: ColorSpace, to: ColorSpace) -> Color {
+        if from == to || from == ColorSpace::Raw || to == ColorSpace::Raw {
+            return c;
+        }
+
+        to.from_xyz(from.to_xyz(c))
+    }
+
+    /// Luminance (CIE Y) of a color in this space.
+    pub fn luminance(self, c: Color) -> f32 {
+        self.to_xyz(c).y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACES: [ColorSpace; 5] = [ColorSpace::LinearSrgb, ColorSpace::Rec2020, ColorSpace::AcesCg, ColorSpace::CieXyz, ColorSpace::Srgb];
+
+    #[test]
+    fn conversions_round_trip() {
+        let colors = [Color::new(0.2, 0.5, 0.8), Color::new(1., 1., 1.), Color::new(0.9, 0.05, 0.3), Color::new(0., 0., 0.)];
+
+        for from in SPACES {
+            for to in SPACES {
+                for c in colors {
+                    let back = ColorSpace::convert(ColorSpace::convert(c, from, to), to, from);
+                    let error = (back - c).x.abs().max((back - c).y.abs()).max((back - c).z.abs());
+                    assert!(error < 1e-4, "{:?} -> {:?}", from, to);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn white_keeps_luminance() {
+        // D65 white is (1, 1, 1) in the D65 spaces, ACEScg adapts it to its own white
+        let white = ColorSpace::LinearSrgb.to_xyz(Color::new(1., 1., 1.));
+        assert!((white.y - 1.).abs() < 1e-4);
+
+        for space in SPACES {
+            let c = space.from_xyz(white);
+            assert!((space.luminance(c) - 1.).abs() < 1e-4, "{:?}", space);
+        }
+        let c = ColorSpace::AcesCg.from_xyz(white);
+        assert!((c.x - 1.).abs() < 2e-3 && (c.y - 1.).abs() < 2e-3 && (c.z - 1.).abs() < 2e-3);
+    }
+
+    #[test]
+    fn raw_is_never_converted() {
+        let c = Color::new(0.25, 0.5, 2.);
+        for space in SPACES {
+            let raw = ColorSpace::convert(c, ColorSpace::Raw, space);
+            assert_eq!([raw.x, raw.y, raw.z], [c.x, c.y, c.z]);
+        }
+    }
+}
@@ -207,14 +207,14 @@ impl Gnoise {
         let f = p.fract();
 
         let v: [f32; 8] = [
-            Vector3::dot(&((Xorshift::rand33((n)) - 0.5).normalized()), f),
-            Vector3::dot(&((Xorshift::rand33((Vector3{x: 1., y: 0., z: 0.} + n)) - 0.5).normalized()), (f - Vector3{x: 1., y: 0., z: 0.})),
-            Vector3::dot(&((Xorshift::rand33((Vector3{x: 0., y: 1., z: 0.} + n)) - 0.5).normalized()), (f - Vector3{x: 0., y: 1., z: 0.})),
-            Vector3::dot(&((Xorshift::rand33((Vector3{x: 1., y: 1., z: 0.} + n)) - 0.5).normalized()), (f - Vector3{x: 1., y: 1., z: 0.})),
-            Vector3::dot(&((Xorshift::rand33((Vector3{x: 0., y: 0., z: 1.} + n)) - 0.5).normalized()), (f - Vector3{x: 0., y: 0., z: 1.})),
-            Vector3::dot(&((Xorshift::rand33((Vector3{x: 1., y: 0., z: 1.} + n)) - 0.5).normalized()), (f - Vector3{x: 1., y: 0., z: 1.})),
-            Vector3::dot(&((Xorshift::rand33((Vector3{x: 0., y: 1., z: 1.} + n)) - 0.5).normalized()), (f - Vector3{x: 0., y: 1., z: 1.})),
-            Vector3::dot(&((Xorshift::rand33((Vector3{x: 1., y: 1., z: 1.} + n)) - 0.5).normalized()), (f - Vector3{x: 1., y: 1., z: 1.})),
+            Vector3::dot(&((Xorshift::rand33(n) - 0.5).normalized()), f),
+            Vector3::dot(&((Xorshift::rand33(Vector3{x: 1., y: 0., z: 0.} + n) - 0.5).normalized()), f - Vector3{x: 1., y: 0., z: 0.}),
+            Vector3::dot(&((Xorshift::rand33(Vector3{x: 0., y: 1., z: 0.} + n) - 0.5).normalized()), f - Vector3{x: 0., y: 1., z: 0.}),
+            Vector3::dot(&((Xorshift::rand33(Vector3{x: 1., y: 1., z: 0.} + n) - 0.5).normalized()), f - Vector3{x: 1., y: 1., z: 0.}),
+            Vector3::dot(&((Xorshift::rand33(Vector3{x: 0., y: 0., z: 1.} + n) - 0.5).normalized()), f - Vector3{x: 0., y: 0., z: 1.}),
+            Vector3::dot(&((Xorshift::rand33(Vector3{x: 1., y: 0., z: 1.} + n) - 0.5).normalized()), f - Vector3{x: 1., y: 0., z: 1.}),
+            Vector3::dot(&((Xorshift::rand33(Vector3{x: 0., y: 1., z: 1.} + n) - 0.5).normalized()), f - Vector3{x: 0., y: 1., z: 1.}),
+            Vector3::dot(&((Xorshift::rand33(Vector3{x: 1., y: 1., z: 1.} + n) - 0.5).normalized()), f - Vector3{x: 1., y: 1., z: 1.}),
         ];
 
         let f0 = hermite5(f.x);
@@ -231,9 +231,9 @@ impl Gnoise {
 #[allow(dead_code)]
 impl Perlin {
 
-    const K: f32 = 0.70710678;
-    const C_PI8: f32 = 0.92387953;
-    const S_PI8: f32 = 0.38268343;
+    const K: f32 = std::f32::consts::FRAC_1_SQRT_2;
+    const C_PI8: f32 = 0.923_879_5;
+    const S_PI8: f32 = 0.382_683_43;
 
     fn gtable2(lattice: Vector2, p: Vector2) -> f32 {
         let n: Array<u32, 2> = Array{data: [lattice.x.to_bits(), lattice.y.to_bits()]}; 
@@ -0,0 +1,196 @@
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+use crate::math::vector::{Point2D, Vector3};
+
+/// Maps the unit square to the unit disk, Shirley & Chiu concentric mapping.
+pub fn concentric_disk(u: Point2D) -> Point2D {
+    let o = u * 2. - 1.;
+
+    if o.x == 0. && o.y == 0. {
+        return Point2D::zero();
+    }
+
+    let (r, theta) = if o.x.abs() > o.y.abs() {
+        (o.x, FRAC_PI_4 * (o.y / o.x))
+    } else {
+        (o.y, FRAC_PI_2 - FRAC_PI_4 * (o.x / o.y))
+    };
+
+    Point2D::new(r * theta.cos(), r * theta.sin())
+}
+
+/// Cosine weighted direction around +z, pdf = cos / pi.
+pub fn cosine_hemisphere(u: Point2D) -> Vector3 {
+    let d = concentric_disk(u);
+    let z = (1. - d.x * d.x - d.y * d.y).max(0.).sqrt();
+
+    Vector3::new(d.x, d.y, z)
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f32) -> f32 {
+    cos_theta.max(0.) / PI
+}
+
+/// Uniform direction on the unit sphere, pdf = 1 / 4pi.
+#[allow(dead_code)]
+pub fn uniform_sphere(u: Point2D) -> Vector3 {
+    let z = 1. - 2. * u.x;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * u.y;
+
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+#[allow(dead_code)]
+pub fn uniform_sphere_pdf() -> f32 {
+    1. / (4. * PI)
+}
+
+/// Piecewise constant distribution over [0, 1) proportional to `func`, sampled by inverting its CDF.
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32
+}
+
+#[allow(dead_code)]
+impl Distribution1D {
+    /// Negative values count as 0, an all zero function is sampled uniformly.
+    pub fn new(func: &[f32]) -> Self {
+        let n = func.len().max(1);
+        let func: Vec<f32> = (0..n).map(|i| func.get(i).copied().unwrap_or(0.).max(0.)).collect();
+
+        let mut cdf = vec![0.; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f32;
+        }
+
+        let integral = cdf[n];
+        if integral > 0. {
+            cdf.iter_mut().for_each(|c| *c /= integral);
+        } else {
+            cdf.iter_mut().enumerate().for_each(|(i, c)| *c = i as f32 / n as f32);
+        }
+
+        Self { func, cdf, integral }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Average of the function over [0, 1).
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Sample in [0, 1), its pdf and the segment it lies in.
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let n = self.count();
+        let i = self.cdf.partition_point(|&c| c <= u).clamp(1, n) - 1;
+
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0. { (u - self.cdf[i]) / width } else { 0. };
+
+        let pdf = if self.integral > 0. { self.func[i] / self.integral } else { 1. };
+        (((i as f32 + du) / n as f32).min(1. - f32::EPSILON), pdf, i)
+    }
+
+    pub fn pdf(&self, x: f32) -> f32 {
+        let i = ((x * self.count() as f32) as usize).min(self.count() - 1);
+        if self.integral > 0. { self.func[i] / self.integral } else { 1. }
+    }
+}
+
+/// Piecewise constant distribution over [0, 1)^2 of a `width` x `height` grid, rows first.
+/// The marginal picks a row, the row's conditional picks a column.
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D
+}
+
+#[allow(dead_code)]
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        let rows: Vec<Distribution1D> = (0..height).map(|y| Distribution1D::new(&func[y * width..(y + 1) * width])).collect();
+        let marginal = Distribution1D::new(&rows.iter().map(|r| r.integral()).collect::<Vec<f32>>());
+
+        Self { rows, marginal }
+    }
+
+    /// Sample (x, y) in [0, 1)^2 and its pdf.
+    pub fn sample(&self, u: Point2D) -> (Point2D, f32) {
+        let (y, pdf_y, row) = self.marginal.sample(u.y);
+        let (x, pdf_x, _) = self.rows[row].sample(u.x);
+
+        (Point2D::new(x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, p: Point2D) -> f32 {
+        let row = ((p.y * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        self.marginal.pdf(p.y) * self.rows[row].pdf(p.x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distribution_1d_sample_matches_pdf() {
+        let d = Distribution1D::new(&[1., 0., 3., -2., 4.]);
+        assert!((d.integral() - 8. / 5.).abs() < 1e-6);
+
+        let n = 100_000;
+        let mut histogram = [0usize; 5];
+        for k in 0..n {
+            let (x, pdf, i) = d.sample((k as f32 + 0.5) / n as f32);
+            assert!((0. ..1.).contains(&x));
+            assert_eq!(i, (x * 5.) as usize);
+            assert!((pdf - d.pdf(x)).abs() < 1e-6);
+            histogram[i] += 1;
+        }
+
+        // segments are chosen in proportion to their pdf, negative values count as 0
+        for (i, &count) in histogram.iter().enumerate() {
+            let expected = d.pdf((i as f32 + 0.5) / 5.) / 5.;
+            assert!((count as f32 / n as f32 - expected).abs() < 1e-3, "segment {}", i);
+        }
+        assert_eq!(histogram[1] + histogram[3], 0);
+    }
+
+    #[test]
+    fn distribution_1d_of_zeros_is_uniform() {
+        let d = Distribution1D::new(&[0., 0., 0., 0.]);
+        let (x, pdf, i) = d.sample(0.6);
+        assert!((x - 0.6).abs() < 1e-6);
+        assert_eq!((pdf, i, d.pdf(0.1)), (1., 2, 1.));
+    }
+
+    #[test]
+    fn distribution_2d_sample_matches_pdf() {
+        let (width, height) = (4, 3);
+        let func: Vec<f32> = (0..width * height).map(|i| ((i * 7) % 5) as f32).collect();
+        let d = Distribution2D::new(&func, width, height);
+
+        let n = 400;
+        let mut histogram = vec![0usize; width * height];
+        for j in 0..n {
+            for i in 0..n {
+                let u = Point2D::new((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                let (p, pdf) = d.sample(u);
+                assert!((pdf - d.pdf(p)).abs() < 1e-5);
+
+                let (x, y) = ((p.x * width as f32) as usize, (p.y * height as f32) as usize);
+                histogram[x + y * width] += 1;
+            }
+        }
+
+        // the pdf is the function normalized to integrate to 1 over the unit square
+        let mean = func.iter().sum::<f32>() / func.len() as f32;
+        for (i, &count) in histogram.iter().enumerate() {
+            let expected = func[i] / mean / (width * height) as f32;
+            assert!((count as f32 / (n * n) as f32 - expected).abs() < 2e-3, "cell {}", i);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+#[allow(dead_code)]
 pub fn equal(a: f32, b: f32) -> bool {
     (a - b).abs() < 1e-6
 }
\ No newline at end of file
@@ -15,6 +15,7 @@ pub struct Vector3
     pub z: f32
 }
 
+#[allow(dead_code)]
 #[derive(Copy, Clone, Default)]
 pub struct Vector4
 {
@@ -30,8 +31,8 @@ pub type Color = Vector3;
 impl Vector2 {
     pub fn new(x: f32, y: f32) -> Self {
         Self {
-            x: x,
-            y: y
+            x,
+            y
         }
     }
 
@@ -651,7 +652,62 @@ impl ops::Neg for Vector3 {
 pub type Point3D = Vector3;
 
 
+/// Orthonormal basis, `w` is the "up" axis (usually a surface normal).
+#[derive(Copy, Clone, Default)]
+pub struct Onb
+{
+    pub u: Vector3,
+    pub v: Vector3,
+    pub w: Vector3
+}
+
+#[allow(dead_code)]
+impl Onb {
+    /// Builds a basis around `n` (must be normalized).
+    /// Duff et al. 2017, "Building an Orthonormal Basis, Revisited".
+    pub fn from_w(n: Vector3) -> Self {
+        let sign = 1.0_f32.copysign(n.z);
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+
+        Self {
+            u: Vector3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+            v: Vector3::new(b, sign + n.y * n.y * a, -n.y),
+            w: n
+        }
+    }
+
+    /// Builds a basis around `n` whose `u` axis follows the tangent `t` as closely as possible.
+    /// Falls back to `from_w` when `t` is degenerate or parallel to `n`.
+    pub fn from_wu(n: Vector3, t: Vector3) -> Self {
+        let u = t - n * n.dot(t);
+        let len = u.magnitude_squared();
+
+        if len < 1e-12 {
+            return Self::from_w(n);
+        }
 
+        let u = u / len.sqrt();
+
+        Self {
+            u,
+            v: n.cross(u),
+            w: n
+        }
+    }
+
+    pub fn to_local(self, a: Vector3) -> Vector3 {
+        Vector3::new(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
+
+    pub fn to_world(self, a: Vector3) -> Vector3 {
+        self.u * a.x + self.v * a.y + self.w * a.z
+    }
+}
+
+
+
+#[allow(dead_code)]
 impl Vector4 {
 
     pub fn new(v: f32) -> Self {
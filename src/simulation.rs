@@ -2,4 +2,30 @@ pub mod engine;
 pub mod result_image;
 pub mod ray;
 pub mod hittable;
-pub mod camera;
\ No newline at end of file
+pub mod camera;
+pub mod light;
+pub mod bdpt;
+pub mod mlt;
+pub mod photon;
+pub mod stereo;
+pub mod lens;
+pub mod aperture;
+pub mod texture;
+pub mod bump;
+pub mod sampler;
+pub mod microfacet;
+pub mod bsdf;
+pub mod material;
+pub mod principled;
+pub mod spectrum;
+pub mod filter;
+pub mod film;
+pub mod adaptive;
+pub mod checkpoint;
+pub mod progress;
+pub mod aov;
+pub mod exr;
+pub mod denoise;
+pub mod firefly;
+pub mod post;
+pub mod exposure;
\ No newline at end of file
@@ -0,0 +1,132 @@
+use crate::math::vector::Color;
+
+/// Sample budget for adaptive sampling. Every pixel takes `min_samples`, then batches of `batch`
+/// samples until its relative standard error drops below `threshold` or it reaches `max_samples`.
+#[derive(Copy, Clone, Debug)]
+pub struct AdaptiveSampling {
+    pub min_samples: i32,
+    pub max_samples: i32,
+    pub batch: i32,
+    pub threshold: f32
+}
+
+#[allow(dead_code)]
+impl AdaptiveSampling {
+    pub fn new(min_samples: i32, max_samples: i32, threshold: f32) -> Self {
+        let min_samples = min_samples.max(2);
+        Self {
+            min_samples,
+            max_samples: max_samples.max(min_samples),
+            batch: 16,
+            threshold
+        }
+    }
+
+    pub fn with_batch(mut self, batch: i32) -> Self {
+        self.batch = batch.max(1);
+        self
+    }
+}
+
+/// Running mean and variance (Welford) of the luminance of the samples taken for one pixel.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct PixelStats {
+    count: u32,
+    mean: f32,
+    m2: f32
+}
+
+#[allow(dead_code)]
+impl PixelStats {
+    pub fn add(&mut self, value: f32) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn from_raw(count: u32, mean: f32, m2: f32) -> Self {
+        Self { count, mean, m2 }
+    }
+
+    /// Count, mean and sum of squared differences, for serialization.
+    pub fn raw(&self) -> (u32, f32, f32) {
+        (self.count, self.mean, self.m2)
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f32 {
+        self.mean
+    }
+
+    /// Unbiased sample variance.
+    pub fn variance(&self) -> f32 {
+        if self.count < 2 {
+            return 0.;
+        }
+
+        self.m2 / (self.count - 1) as f32
+    }
+
+    /// Standard error of the mean relative to the mean. The small offset keeps dark pixels
+    /// from needing an unbounded number of samples.
+    pub fn relative_error(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+
+        (self.variance() / self.count as f32).sqrt() / (self.mean.abs() + 1e-3)
+    }
+}
+
+/// Heatmap color for a pixel that took `count` samples out of at most `max`, blue for few and red for many.
+pub fn heat(count: u32, max: u32) -> Color {
+    let t = (count as f32 / max.max(1) as f32).clamp(0., 1.);
+
+    if t < 0.5 {
+        Color::lerp(Color::new(0., 0., 1.), Color::new(0., 1., 0.), t * 2.)
+    } else {
+        Color::lerp(Color::new(0., 1., 0.), Color::new(1., 0., 0.), t * 2. - 1.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_stats_match_two_pass_variance() {
+        let values = [0.5, 2., 1.25, 0., 3.5, 1., 1.75, 0.25];
+        let mut stats = PixelStats::default();
+        for v in values {
+            stats.add(v);
+        }
+
+        let n = values.len() as f32;
+        let mean = values.iter().sum::<f32>() / n;
+        let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / (n - 1.);
+
+        assert_eq!(stats.count(), 8);
+        assert!((stats.mean() - mean).abs() < 1e-6);
+        assert!((stats.variance() - variance).abs() < 1e-5);
+        assert!((stats.relative_error() - (variance / n).sqrt() / (mean + 1e-3)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn pixel_stats_need_two_samples() {
+        let mut stats = PixelStats::default();
+        stats.add(4.);
+
+        assert_eq!(stats.variance(), 0.);
+        assert_eq!(stats.relative_error(), f32::INFINITY);
+
+        // constant samples have no variance, also after a round trip through the raw values
+        stats.add(4.);
+        let (count, mean, m2) = stats.raw();
+        let restored = PixelStats::from_raw(count, mean, m2);
+        assert_eq!((restored.count(), restored.mean(), restored.variance()), (2, 4., 0.));
+    }
+}
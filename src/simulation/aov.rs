@@ -0,0 +1,144 @@
+use crate::math::vector::Color;
+
+/// Arbitrary output variables, per pixel data written next to the beauty image for compositing.
+#[allow(dead_code)]
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Aov {
+    /// `HitRecord::t` of the camera ray.
+    Depth,
+    /// World space shading normal at the first hit.
+    Normal,
+    /// World space position of the first hit.
+    Position,
+    /// Reflectance of the first hit's material.
+    Albedo,
+    /// Index of the hit object in the world list.
+    ObjectId,
+    MaterialId,
+    /// Light reaching the camera after one bounce.
+    Direct,
+    /// Light reaching the camera after two or more bounces.
+    Indirect,
+    /// Light emitted by the first hit, or the background seen directly.
+    Emission,
+    /// Number of samples taken, filled in from the film statistics.
+    SampleCount
+}
+
+/// How samples of one pixel are combined.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AovMode {
+    Average,
+    /// Keeps the first value, for ids that can not be averaged.
+    First
+}
+
+/// Where the AOVs are written, file names are derived from the output file name.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AovOutput {
+    /// One EXR holding the beauty image and every AOV as named channels.
+    MultiChannel,
+    /// One EXR per AOV, `output.ppm` gives `output_depth.exr`, `output_normal.exr`, ...
+    Separate
+}
+
+const COUNT: usize = 10;
+
+#[allow(dead_code)]
+impl Aov {
+    pub const ALL: [Aov; COUNT] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Position,
+        Aov::Albedo,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Direct,
+        Aov::Indirect,
+        Aov::Emission,
+        Aov::SampleCount
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Emission => "emission",
+            Aov::SampleCount => "sample_count"
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Aov::ALL.into_iter().find(|a| a.name() == name)
+    }
+
+    /// Channel names, scalars use a single luminance channel so viewers show them as grey.
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth | Aov::ObjectId | Aov::MaterialId | Aov::SampleCount => &["Y"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Albedo | Aov::Direct | Aov::Indirect | Aov::Emission => &["R", "G", "B"]
+        }
+    }
+
+    /// Lighting AOVs are radiance and go through the same color conversion as the beauty image.
+    pub fn is_radiance(self) -> bool {
+        matches!(self, Aov::Direct | Aov::Indirect | Aov::Emission)
+    }
+
+    pub fn mode(self) -> AovMode {
+        match self {
+            Aov::ObjectId | Aov::MaterialId => AovMode::First,
+            _ => AovMode::Average
+        }
+    }
+}
+
+/// AOV values of one camera sample, written by the pixel function. Scalars are stored in every component.
+#[derive(Copy, Clone, Default)]
+pub struct AovSample {
+    values: [Color; COUNT],
+    written: u16
+}
+
+#[allow(dead_code)]
+impl AovSample {
+    pub fn set(&mut self, aov: Aov, value: Color) {
+        self.values[aov as usize] = value;
+        self.written |= 1 << aov as u16;
+    }
+
+    pub fn set_scalar(&mut self, aov: Aov, value: f32) {
+        self.set(aov, Color::new(value, value, value));
+    }
+
+    /// Accumulates into the AOV, for contributions gathered over several bounces.
+    pub fn add(&mut self, aov: Aov, value: Color) {
+        let current = self.get(aov).unwrap_or(Color::zero());
+        self.set(aov, current + value);
+    }
+
+    pub fn get(&self, aov: Aov) -> Option<Color> {
+        if self.written & (1 << aov as u16) == 0 {
+            return None;
+        }
+
+        Some(self.values[aov as usize])
+    }
+
+    pub fn map_radiance<M: Fn(Color) -> Color>(&mut self, map: M) {
+        for aov in Aov::ALL.into_iter().filter(|a| a.is_radiance()) {
+            if let Some(v) = self.get(aov) {
+                self.values[aov as usize] = map(v);
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::f32::consts::TAU;
+use std::hash::Hasher;
+use std::io;
+
+use crate::math::color::ColorSpace;
+use crate::math::sampling::{self, Distribution2D};
+use crate::math::vector::Point2D;
+use crate::simulation::checkpoint::SceneHasher;
+use crate::simulation::texture::ImageTexture;
+
+/// Shape of a thin lens aperture, which out of focus highlights take on. Shapes fit in the unit
+/// disk and are sampled uniformly over their open area, so changing the shape does not change exposure.
+pub enum Aperture {
+    Circular,
+    /// Regular polygon of `blades` straight blades, its first corner `rotation` radians from the right.
+    Polygonal { blades: i32, rotation: f32 },
+    Image(ApertureImage)
+}
+
+/// Aperture mask from an image, sampled proportionally to its luminance. The image is fit into
+/// the square around the unit disk, its top row towards the camera's up.
+pub struct ApertureImage {
+    width: i32,
+    height: i32,
+    mask: Vec<f32>,
+    distribution: Distribution2D
+}
+
+#[allow(dead_code)]
+impl ApertureImage {
+    pub fn new(width: i32, height: i32, mask: Vec<f32>) -> Self {
+        let distribution = Distribution2D::new(&mask, width as usize, height as usize);
+        Self { width, height, mask, distribution }
+    }
+
+    /// Loads a PPM image, white is open and black is closed.
+    pub fn load(file_name: &str) -> io::Result<Self> {
+        let image = ImageTexture::load_tagged(file_name, ColorSpace::Raw, ColorSpace::Raw)?;
+        let (width, height) = image.size();
+
+        let mask = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let c = image.texel(x, y);
+                (c.x + c.y + c.z) / 3.
+            })
+            .collect();
+
+        Ok(Self::new(width, height, mask))
+    }
+}
+
+#[allow(dead_code)]
+impl Aperture {
+    pub fn polygonal(blades: i32, rotation: f32) -> Self {
+        Aperture::Polygonal { blades: blades.max(3), rotation }
+    }
+
+    /// Point on the aperture in the unit disk for a uniform sample `u` in [0, 1)^2.
+    pub fn sample(&self, u: Point2D) -> Point2D {
+        match self {
+            Aperture::Circular => sampling::concentric_disk(u),
+            Aperture::Polygonal { blades, rotation } => {
+                // the polygon is a fan of equal triangles around the center, pick one and sample it uniformly
+                let n = *blades as f32;
+                let scaled = u.x * n;
+                let i = scaled.floor().min(n - 1.);
+                let u1 = scaled - i;
+
+                let a0 = rotation + TAU * i / n;
+                let a1 = rotation + TAU * (i + 1.) / n;
+
+                let s = u1.sqrt();
+                let t = u.y;
+                Point2D::new(
+                    s * ((1. - t) * a0.cos() + t * a1.cos()),
+                    s * ((1. - t) * a0.sin() + t * a1.sin())
+                )
+            }
+            Aperture::Image(image) => {
+                let (p, _) = image.distribution.sample(u);
+                Point2D::new(2. * p.x - 1., 1. - 2. * p.y)
+            }
+        }
+    }
+
+    pub fn fingerprint(&self, h: &mut SceneHasher) {
+        match self {
+            Aperture::Circular => h.write_str("Circular"),
+            Aperture::Polygonal { blades, rotation } => {
+                h.write_str("Polygonal");
+                h.write_i32(*blades);
+                h.write_f32(*rotation);
+            }
+            Aperture::Image(image) => {
+                h.write_str("Image");
+                h.write_i32(image.width);
+                h.write_i32(image.height);
+                for &m in &image.mask {
+                    h.write_f32(m);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,592 @@
+use crate::math::sampling;
+use crate::math::vector::{Color, Onb, Point2D, Point3D, Vector3};
+use crate::simulation::aov::Aov;
+use crate::simulation::bsdf::{Bsdf, DiffuseBxdf};
+use crate::simulation::engine::PixelContext;
+use crate::simulation::hittable::{HitRecord, Hittable, HittableList};
+use crate::simulation::light::LightList;
+use crate::simulation::ray::Ray;
+use crate::simulation::spectrum::Wavelength;
+
+/// Ray extent, the same the path tracer uses.
+const T_MIN: f32 = 0.001;
+const T_MAX: f32 = 1000.;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Camera,
+    Light,
+    Surface
+}
+
+/// Quantity a subpath carries: camera subpaths gather radiance, light subpaths carry importance.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Transport {
+    Radiance,
+    Importance
+}
+
+struct Vertex {
+    kind: Kind,
+    p: Point3D,
+    /// Geometric and shading normal on the side the path arrived from, zero off surfaces.
+    ng: Vector3,
+    ns: Vector3,
+    /// Towards the previous vertex of the subpath.
+    wo: Vector3,
+    /// Throughput of the subpath from its start up to this vertex.
+    beta: Color,
+    /// Area densities of sampling this vertex from the previous one, and in reverse from the next one.
+    pdf_fwd: f32,
+    pdf_rev: f32,
+    /// Scattered by a specular lobe or otherwise impossible to connect to.
+    delta: bool,
+    rec: Option<HitRecord>,
+    bsdf: Option<Bsdf>
+}
+
+impl Vertex {
+    /// Camera vertex at `p`, `delta` when the camera can not be reached by light paths.
+    fn camera(p: Point3D, beta: Color, delta: bool) -> Self {
+        Self {
+            kind: Kind::Camera,
+            p,
+            ng: Vector3::zero(),
+            ns: Vector3::zero(),
+            wo: Vector3::zero(),
+            beta,
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+            delta,
+            rec: None,
+            bsdf: None
+        }
+    }
+
+    /// Point sampled on an area light, `rec` seen from outside and `normal` on the emitting side.
+    fn light(rec: HitRecord, normal: Vector3, beta: Color, pdf_fwd: f32) -> Self {
+        Self {
+            kind: Kind::Light,
+            p: rec.p(),
+            ng: normal,
+            ns: normal,
+            wo: Vector3::zero(),
+            beta,
+            pdf_fwd,
+            pdf_rev: 0.,
+            delta: false,
+            rec: Some(rec),
+            bsdf: None
+        }
+    }
+
+    /// Surface hit by `ray`. Objects without material scatter like grey diffuse surfaces.
+    fn surface(rec: HitRecord, ray: &Ray, beta: Color) -> Self {
+        let bsdf = match rec.material() {
+            Some(material) => material.bsdf(&rec),
+            None => Bsdf::new(rec.shading_frame(), Box::new(DiffuseBxdf::new(Color::new(0.5, 0.5, 0.5))))
+        };
+
+        Self {
+            kind: Kind::Surface,
+            p: rec.p(),
+            ng: rec.geometric_normal(),
+            ns: rec.normal(),
+            wo: -ray.direction().normalized(),
+            beta,
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+            delta: false,
+            rec: Some(rec),
+            bsdf: Some(bsdf)
+        }
+    }
+
+    fn is_on_surface(&self) -> bool {
+        self.ng.magnitude_squared() > 0.
+    }
+
+    fn object_id(&self) -> u32 {
+        self.rec.as_ref().map_or(0, |rec| rec.object_id())
+    }
+
+    fn is_light(&self, lights: &LightList) -> bool {
+        self.kind == Kind::Light || lights.is_light(self.object_id())
+    }
+
+    /// Normal on the side an area light at this vertex emits from.
+    fn emitting_normal(&self) -> Vector3 {
+        match &self.rec {
+            Some(rec) if !rec.front_face() => -self.ng,
+            _ => self.ng
+        }
+    }
+
+    /// Light emitted from this vertex towards `to`.
+    fn le(&self, to: &Vertex) -> Color {
+        let Some(rec) = &self.rec else {
+            return Color::zero();
+        };
+        if self.emitting_normal().dot(to.p - self.p) <= 0. {
+            return Color::zero();
+        }
+
+        rec.material().map_or(Color::zero(), |m| m.emitted(rec))
+    }
+
+    /// Scattering from the previous vertex towards `next`. Light subpaths undo the radiance
+    /// compression of refraction and correct for shading normals, as `random_walk` does.
+    fn f(&self, next: &Vertex, mode: Transport) -> Color {
+        let Some(bsdf) = &self.bsdf else {
+            return Color::zero();
+        };
+
+        let wi = (next.p - self.p).normalized();
+        let f = bsdf.f(self.wo, wi);
+        match mode {
+            Transport::Radiance => f,
+            Transport::Importance => {
+                let eta = bsdf.eta(self.wo, wi);
+                f * (eta * eta * shading_correction(self, self.wo, wi))
+            }
+        }
+    }
+
+    /// Turns a density over directions at this vertex into a density over the area at `next`.
+    fn convert_density(&self, pdf: f32, next: &Vertex) -> f32 {
+        let w = next.p - self.p;
+        let dist2 = w.magnitude_squared();
+        if dist2 == 0. {
+            return 0.;
+        }
+
+        let mut pdf = pdf / dist2;
+        if next.is_on_surface() {
+            pdf *= next.ng.dot(w / dist2.sqrt()).abs();
+        }
+
+        pdf
+    }
+
+    /// Area density of sampling `next` from this vertex, reached from `prev`.
+    fn pdf(&self, ctx: &PixelContext, prev: Option<&Vertex>, next: &Vertex) -> f32 {
+        if self.kind == Kind::Light {
+            return self.pdf_light(next);
+        }
+
+        let wn = (next.p - self.p).normalized();
+        let pdf = match (self.kind, &self.bsdf, prev) {
+            (Kind::Camera, _, _) => ctx.camera.pdf_direction(&Ray::new(&self.p, &wn)),
+            (_, Some(bsdf), Some(prev)) => bsdf.pdf((prev.p - self.p).normalized(), wn),
+            _ => 0.
+        };
+
+        self.convert_density(pdf, next)
+    }
+
+    /// Area density at `next` of a light path leaving this point of an area light.
+    fn pdf_light(&self, next: &Vertex) -> f32 {
+        let w = next.p - self.p;
+        let dist2 = w.magnitude_squared();
+        if dist2 == 0. {
+            return 0.;
+        }
+        let w = w / dist2.sqrt();
+
+        let mut pdf = sampling::cosine_hemisphere_pdf(self.emitting_normal().dot(w)) / dist2;
+        if next.is_on_surface() {
+            pdf *= next.ng.dot(w).abs();
+        }
+
+        pdf
+    }
+
+    /// Area density of a light path starting at this point of an area light.
+    fn pdf_light_origin(&self, lights: &LightList) -> f32 {
+        lights.pdf_area(self.object_id())
+    }
+}
+
+/// Adjoint correction for shading normals on light subpaths (Veach 1997, 5.3), `wo` towards the light.
+fn shading_correction(v: &Vertex, wo: Vector3, wi: Vector3) -> f32 {
+    let denom = wo.dot(v.ng).abs() * wi.dot(v.ns).abs();
+    if denom == 0. {
+        return 0.;
+    }
+
+    wo.dot(v.ns).abs() * wi.dot(v.ng).abs() / denom
+}
+
+/// Extends `path` by sampling the BSDF at each hit, for at most `max_depth` more vertices. `pdf`
+/// is the density over directions `ray` was sampled with. Returns the background seen when a
+/// camera subpath leaves the scene.
+#[allow(clippy::too_many_arguments)]
+fn random_walk(world: &HittableList, ctx: &mut PixelContext, background: &dyn Fn(&Ray) -> Color, mut ray: Ray, mut beta: Color, pdf: f32, mode: Transport, max_depth: usize, path: &mut Vec<Vertex>) -> Color {
+    let mut pdf_fwd = pdf;
+    let mut bounces = 0;
+
+    while bounces < max_depth {
+        ctx.rays += 1;
+
+        let Some(rec) = world.hit(&ray, T_MIN, T_MAX) else {
+            if mode == Transport::Radiance {
+                return beta * background(&ray);
+            }
+            break;
+        };
+
+        let prev = path.len() - 1;
+        let mut v = Vertex::surface(rec, &ray, beta);
+        v.pdf_fwd = path[prev].convert_density(pdf_fwd, &v);
+
+        bounces += 1;
+        if bounces >= max_depth {
+            path.push(v);
+            break;
+        }
+
+        let uc = ctx.sampler.next_1d();
+        let u = ctx.sampler.next_2d();
+
+        let Some(bsdf) = &v.bsdf else {
+            path.push(v);
+            break;
+        };
+        let Some(s) = bsdf.sample_f(v.wo, uc, u) else {
+            path.push(v);
+            break;
+        };
+
+        pdf_fwd = s.pdf;
+        let mut pdf_rev = bsdf.pdf(s.wi, v.wo);
+        if s.specular {
+            v.delta = true;
+            pdf_fwd = 0.;
+            pdf_rev = 0.;
+        }
+
+        beta = beta * s.f * (s.wi.dot(v.ns).abs() / s.pdf);
+        if mode == Transport::Importance {
+            beta *= s.eta * s.eta * shading_correction(&v, v.wo, s.wi);
+        }
+
+        path[prev].pdf_rev = v.convert_density(pdf_rev, &path[prev]);
+
+        ray = ray.spawn(&v.p, &s.wi);
+        path.push(v);
+
+        if beta.x <= 0. && beta.y <= 0. && beta.z <= 0. {
+            break;
+        }
+    }
+
+    Color::zero()
+}
+
+/// Starts a light subpath on a randomly chosen area light, leaving it cosine distributed.
+fn light_subpath(world: &HittableList, ctx: &mut PixelContext, wavelength: Option<Wavelength>, max_depth: usize) -> Vec<Vertex> {
+    let mut path = Vec::with_capacity(max_depth + 1);
+
+    let uc = ctx.sampler.next_1d();
+    let u = ctx.sampler.next_2d();
+    let u_dir = ctx.sampler.next_2d();
+
+    let Some(ls) = ctx.lights.sample(world, uc, u, wavelength) else {
+        return path;
+    };
+
+    let local = sampling::cosine_hemisphere(u_dir);
+    let pdf_dir = sampling::cosine_hemisphere_pdf(local.z);
+    let le = ls.rec.material().map_or(Color::zero(), |m| m.emitted(&ls.rec));
+    if pdf_dir <= 0. || ls.pdf_area <= 0. || (le.x <= 0. && le.y <= 0. && le.z <= 0.) {
+        return path;
+    }
+
+    let dir = Onb::from_w(ls.normal).to_world(local);
+    let ray = Ray::new(&ls.rec.p(), &dir).with_wavelength(wavelength);
+    let beta = le * (local.z / (ls.pdf_area * pdf_dir));
+
+    path.push(Vertex::light(ls.rec, ls.normal, le, ls.pdf_area));
+    random_walk(world, ctx, &|_| Color::zero(), ray, beta, pdf_dir, Transport::Importance, max_depth, &mut path);
+
+    path
+}
+
+/// Whether the segment between `a` and `b` is free of geometry.
+fn unoccluded(world: &HittableList, ctx: &mut PixelContext, a: Point3D, b: Point3D) -> bool {
+    let d = b - a;
+    let dist = d.magnitude();
+    if dist <= 2. * T_MIN {
+        return false;
+    }
+
+    ctx.rays += 1;
+    world.hit(&Ray::new(&a, &(d / dist)), T_MIN, dist - T_MIN).is_none()
+}
+
+/// Geometry term between two vertices including visibility.
+fn g(world: &HittableList, ctx: &mut PixelContext, a: &Vertex, b: &Vertex) -> f32 {
+    let d = a.p - b.p;
+    let dist2 = d.magnitude_squared();
+    if dist2 == 0. {
+        return 0.;
+    }
+    let d = d / dist2.sqrt();
+
+    let mut g = 1. / dist2;
+    if a.is_on_surface() {
+        g *= a.ns.dot(d).abs();
+    }
+    if b.is_on_surface() {
+        g *= b.ns.dot(d).abs();
+    }
+
+    if g == 0. || !unoccluded(world, ctx, a.p, b.p) {
+        return 0.;
+    }
+
+    g
+}
+
+fn is_black(c: Color) -> bool {
+    c.x <= 0. && c.y <= 0. && c.z <= 0.
+}
+
+/// Unweighted contribution of the path made of `s` light and `t` camera vertices. Strategies with
+/// `s == 1` or `t == 1` sample a fresh light or camera vertex, returned alongside. For `t == 1`
+/// the film position the light arrives at is returned too.
+fn connect(world: &HittableList, ctx: &mut PixelContext, light: &[Vertex], camera: &[Vertex], s: usize, t: usize) -> Option<(Color, Option<Vertex>, Option<Point2D>)> {
+    if s == 0 {
+        let pt = &camera[t - 1];
+        if !pt.is_light(ctx.lights) {
+            return None;
+        }
+
+        return Some((pt.beta * pt.le(&camera[t - 2]), None, None));
+    }
+
+    if t == 1 {
+        let qs = &light[s - 1];
+        if qs.delta {
+            return None;
+        }
+
+        let u = ctx.sampler.next_2d();
+        let sample = ctx.camera.sample_importance(qs.p, u)?;
+        let sampled = Vertex::camera(sample.origin, Color::new(sample.weight, sample.weight, sample.weight), false);
+
+        let mut l = qs.beta * qs.f(&sampled, Transport::Importance) * sampled.beta;
+        if qs.is_on_surface() {
+            l *= (sample.origin - qs.p).normalized().dot(qs.ns).abs();
+        }
+        if is_black(l) || !unoccluded(world, ctx, qs.p, sample.origin) {
+            return None;
+        }
+
+        return Some((l, Some(sampled), Some(sample.film)));
+    }
+
+    if s == 1 {
+        let pt = &camera[t - 1];
+        if pt.delta {
+            return None;
+        }
+
+        let uc = ctx.sampler.next_1d();
+        let u = ctx.sampler.next_2d();
+        let ls = ctx.lights.sample(world, uc, u, pt.rec.as_ref().and_then(|rec| rec.wavelength()))?;
+
+        let mut sampled = Vertex::light(ls.rec, ls.normal, Color::zero(), ls.pdf_area);
+        let wi = sampled.p - pt.p;
+        let dist2 = wi.magnitude_squared();
+        let wi = wi / dist2.sqrt();
+
+        // density over directions at pt of the point chosen on the light
+        let cos_light = sampled.ng.dot(-wi);
+        if cos_light <= 0. {
+            return None;
+        }
+        let pdf = ls.pdf_area * dist2 / cos_light;
+
+        sampled.beta = sampled.le(pt) / pdf;
+        sampled.pdf_fwd = sampled.pdf_light_origin(ctx.lights);
+
+        let mut l = pt.beta * pt.f(&sampled, Transport::Radiance) * sampled.beta;
+        if pt.is_on_surface() {
+            l *= wi.dot(pt.ns).abs();
+        }
+        if is_black(l) || !unoccluded(world, ctx, pt.p, sampled.p) {
+            return None;
+        }
+
+        return Some((l, Some(sampled), None));
+    }
+
+    let qs = &light[s - 1];
+    let pt = &camera[t - 1];
+    if qs.delta || pt.delta {
+        return None;
+    }
+
+    let l = qs.beta * qs.f(pt, Transport::Importance) * pt.f(qs, Transport::Radiance) * pt.beta;
+    if is_black(l) {
+        return None;
+    }
+
+    let g = g(world, ctx, qs, pt);
+    if g == 0. {
+        return None;
+    }
+
+    Some((l * g, None, None))
+}
+
+/// Balance heuristic weight of the (s, t) strategy among all strategies that could have generated
+/// the same path, from the ratios of their vertex densities (Veach 1997, 10.2).
+fn mis_weight(ctx: &PixelContext, light: &[Vertex], camera: &[Vertex], sampled: Option<&Vertex>, s: usize, t: usize) -> f32 {
+    if s + t == 2 {
+        return 1.;
+    }
+
+    // the densities change for this connection, work on copies
+    let densities = |v: &Vertex| (v.pdf_fwd, v.pdf_rev, v.delta);
+    let mut cam: Vec<(f32, f32, bool)> = camera[..t].iter().map(densities).collect();
+    let mut lig: Vec<(f32, f32, bool)> = light[..s].iter().map(densities).collect();
+
+    let pt = if t == 1 { sampled.unwrap() } else { &camera[t - 1] };
+    let qs = match s {
+        0 => None,
+        1 => Some(sampled.unwrap()),
+        _ => Some(&light[s - 1])
+    };
+    let pt_minus = (t > 1).then(|| &camera[t - 2]);
+    let qs_minus = (s > 1).then(|| &light[s - 2]);
+
+    if t == 1 {
+        cam[0] = densities(pt);
+    }
+    if let Some(qs) = qs && s == 1 {
+        lig[0] = densities(qs);
+    }
+
+    // the connection vertices are connectible whatever scattered them
+    cam[t - 1].2 = false;
+    if s > 0 {
+        lig[s - 1].2 = false;
+    }
+
+    cam[t - 1].1 = match qs {
+        Some(qs) => qs.pdf(ctx, qs_minus, pt),
+        None => pt.pdf_light_origin(ctx.lights)
+    };
+    if let Some(pt_minus) = pt_minus {
+        cam[t - 2].1 = match qs {
+            Some(qs) => pt.pdf(ctx, Some(qs), pt_minus),
+            None => pt.pdf_light(pt_minus)
+        };
+    }
+    if let Some(qs) = qs {
+        lig[s - 1].1 = pt.pdf(ctx, pt_minus, qs);
+        if let Some(qs_minus) = qs_minus {
+            lig[s - 2].1 = qs.pdf(ctx, Some(pt), qs_minus);
+        }
+    }
+
+    // a density of 0 marks a specular vertex, whose delta cancels in the ratios
+    let remap = |pdf: f32| if pdf != 0. { pdf } else { 1. };
+
+    let mut sum = 0.;
+    let mut ri = 1.;
+    for i in (1..t).rev() {
+        ri *= remap(cam[i].1) / remap(cam[i].0);
+        if !cam[i].2 && !cam[i - 1].2 {
+            sum += ri;
+        }
+    }
+
+    // area lights have no delta in position
+    let mut ri = 1.;
+    for i in (0..s).rev() {
+        ri *= remap(lig[i].1) / remap(lig[i].0);
+        let delta_light = i > 0 && lig[i - 1].2;
+        if !lig[i].2 && !delta_light {
+            sum += ri;
+        }
+    }
+
+    1. / (1. + sum)
+}
+
+/// Adds light of a path with `depth` bounces, split into the lighting AOVs.
+fn contribute(ctx: &mut PixelContext, depth: usize, light: Color, radiance: &mut Color) {
+    let light = ctx.clamp_light(depth as i32, light);
+    *radiance += light;
+
+    let aov = match depth {
+        0 => Aov::Emission,
+        1 => Aov::Direct,
+        _ => Aov::Indirect
+    };
+    ctx.aov.add(aov, light);
+}
+
+/// Bidirectional path tracing (Veach & Guibas 1995, Lafortune & Willems 1993). A camera subpath
+/// and a light subpath are traced and every prefix of one is connected to every prefix of the
+/// other, each strategy weighted by multiple importance sampling. Light subpath vertices connected
+/// directly to the camera are splatted to the pixel they are seen at. Paths have at most `trace - 1`
+/// bounces like the path tracer. Light is emitted by the emissive objects and by `background`,
+/// which only camera subpaths leaving the scene can find.
+pub fn radiance(ray: &Ray, world: &HittableList, ctx: &mut PixelContext, trace: i32, background: &dyn Fn(&Ray) -> Color) -> Color {
+    let max_depth = (trace - 1).max(0) as usize;
+    let mut radiance = Color::zero();
+
+    let pdf_dir = ctx.camera.pdf_direction(ray);
+    let mut camera = Vec::with_capacity(max_depth + 2);
+    camera.push(Vertex::camera(ray.origin(), Color::new(1., 1., 1.), pdf_dir <= 0.));
+
+    let escaped = random_walk(world, ctx, background, ray.spawn(&ray.origin(), &ray.direction()), Color::new(1., 1., 1.), pdf_dir, Transport::Radiance, max_depth + 1, &mut camera);
+    // only the camera subpath can find the background, so no other strategy competes
+    contribute(ctx, camera.len() - 1, escaped, &mut radiance);
+
+    if let Some(first) = camera.get(1).and_then(|v| v.rec.as_ref()) {
+        ctx.aov.set_scalar(Aov::Depth, first.t());
+        ctx.aov.set(Aov::Normal, first.normal());
+        ctx.aov.set(Aov::Position, first.p());
+        ctx.aov.set_scalar(Aov::ObjectId, first.object_id() as f32);
+        ctx.aov.set_scalar(Aov::MaterialId, first.material_id() as f32);
+        if let Some(material) = first.material() {
+            ctx.aov.set(Aov::Albedo, material.albedo(first));
+        }
+    }
+
+    let light = light_subpath(world, ctx, ray.wavelength(), max_depth);
+
+    for t in 1..=camera.len() {
+        for s in 0..=light.len() {
+            let depth = s + t;
+            if (s == 1 && t == 1) || depth < 2 || depth - 2 > max_depth || (s == 0 && t < 2) {
+                continue;
+            }
+            let depth = depth - 2;
+
+            let Some((l, sampled, film)) = connect(world, ctx, &light, &camera, s, t) else {
+                continue;
+            };
+
+            let l = l * mis_weight(ctx, &light, &camera, sampled.as_ref(), s, t);
+            if is_black(l) {
+                continue;
+            }
+
+            match film {
+                Some(film) => {
+                    let l = ctx.clamp_light(depth as i32, l);
+                    ctx.splats.push((film, l));
+                }
+                None => contribute(ctx, depth, l, &mut radiance)
+            }
+        }
+    }
+
+    radiance
+}
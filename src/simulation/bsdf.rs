@@ -0,0 +1,459 @@
+use std::f32::consts::PI;
+
+use crate::math::sampling;
+use crate::math::vector::{Color, Onb, Point2D, Vector3};
+use crate::simulation::microfacet::{self, TrowbridgeReitz};
+
+#[allow(dead_code)]
+pub struct BsdfSample {
+    pub wi: Vector3,
+    /// BSDF value, for specular lobes already divided by |cos(wi)| so that `f * cos / pdf` holds.
+    pub f: Color,
+    pub pdf: f32,
+    pub specular: bool,
+    /// Relative IOR eta_t / eta_i across a refraction, 1 for reflection. Light paths multiply by
+    /// its square to undo the radiance compression `f` applies.
+    pub eta: f32
+}
+
+/// Scattering function in the local shading frame, +z is the shading normal.
+pub trait Bxdf: Send + Sync {
+    fn f(&self, wo: Vector3, wi: Vector3) -> Color;
+
+    /// `uc` selects between lobes, `u` samples the direction inside the lobe.
+    fn sample_f(&self, wo: Vector3, uc: f32, u: Point2D) -> Option<BsdfSample>;
+
+    fn pdf(&self, wo: Vector3, wi: Vector3) -> f32;
+
+    /// Whether all lobes are specular or narrow glossy ones, surfaces that show a sharp
+    /// reflection or refraction of their surroundings rather than blurring it.
+    fn is_glossy(&self) -> bool {
+        false
+    }
+
+    /// Relative IOR below / above the surface for lobes that refract, 1 when nothing refracts.
+    fn eta(&self) -> f32 {
+        1.
+    }
+}
+
+/// A `Bxdf` placed at a surface point, works on world space directions.
+pub struct Bsdf {
+    frame: Onb,
+    bxdf: Box<dyn Bxdf>
+}
+
+pub struct DiffuseBxdf {
+    r: Color
+}
+
+pub struct ConductorBxdf {
+    distrib: TrowbridgeReitz,
+    eta: Color,
+    k: Color
+}
+
+pub struct DielectricBxdf {
+    distrib: TrowbridgeReitz,
+    eta: f32
+}
+
+fn same_hemisphere(a: Vector3, b: Vector3) -> bool {
+    a.z * b.z > 0.
+}
+
+fn face_forward(n: Vector3, v: Vector3) -> Vector3 {
+    if n.dot(v) < 0. { -n } else { n }
+}
+
+#[allow(dead_code)]
+impl Bsdf {
+    pub fn new(frame: Onb, bxdf: Box<dyn Bxdf>) -> Self {
+        Self { frame, bxdf }
+    }
+
+    pub fn frame(&self) -> Onb {
+        self.frame
+    }
+
+    pub fn f(&self, wo: Vector3, wi: Vector3) -> Color {
+        self.bxdf.f(self.frame.to_local(wo), self.frame.to_local(wi))
+    }
+
+    /// Returned `wi` is in world space.
+    pub fn sample_f(&self, wo: Vector3, uc: f32, u: Point2D) -> Option<BsdfSample> {
+        let wo = self.frame.to_local(wo);
+        if wo.z == 0. {
+            return None;
+        }
+
+        let mut s = self.bxdf.sample_f(wo, uc, u)?;
+        if s.pdf <= 0. || s.wi.z == 0. {
+            return None;
+        }
+
+        s.wi = self.frame.to_world(s.wi);
+        Some(s)
+    }
+
+    pub fn pdf(&self, wo: Vector3, wi: Vector3) -> f32 {
+        self.bxdf.pdf(self.frame.to_local(wo), self.frame.to_local(wi))
+    }
+
+    pub fn is_glossy(&self) -> bool {
+        self.bxdf.is_glossy()
+    }
+
+    /// Relative IOR eta_t / eta_i seen from `wo` when `wi` lies on the other side, as `BsdfSample::eta`.
+    /// 1 for reflection.
+    pub fn eta(&self, wo: Vector3, wi: Vector3) -> f32 {
+        let (wo, wi) = (self.frame.to_local(wo), self.frame.to_local(wi));
+        if same_hemisphere(wo, wi) {
+            return 1.;
+        }
+
+        if wo.z > 0. { self.bxdf.eta() } else { 1. / self.bxdf.eta() }
+    }
+}
+
+impl DiffuseBxdf {
+    pub fn new(r: Color) -> Self {
+        Self { r }
+    }
+}
+
+impl Bxdf for DiffuseBxdf {
+    fn f(&self, wo: Vector3, wi: Vector3) -> Color {
+        if !same_hemisphere(wo, wi) {
+            return Color::zero();
+        }
+
+        self.r / PI
+    }
+
+    fn sample_f(&self, wo: Vector3, _uc: f32, u: Point2D) -> Option<BsdfSample> {
+        let mut wi = sampling::cosine_hemisphere(u);
+        if wo.z < 0. {
+            wi.z = -wi.z;
+        }
+
+        Some(BsdfSample {
+            wi,
+            f: self.r / PI,
+            pdf: sampling::cosine_hemisphere_pdf(wi.z.abs()),
+            specular: false,
+            eta: 1.
+        })
+    }
+
+    fn pdf(&self, wo: Vector3, wi: Vector3) -> f32 {
+        if !same_hemisphere(wo, wi) {
+            return 0.;
+        }
+
+        sampling::cosine_hemisphere_pdf(wi.z.abs())
+    }
+}
+
+impl ConductorBxdf {
+    pub fn new(distrib: TrowbridgeReitz, eta: Color, k: Color) -> Self {
+        Self { distrib, eta, k }
+    }
+}
+
+impl Bxdf for ConductorBxdf {
+    fn f(&self, wo: Vector3, wi: Vector3) -> Color {
+        if !same_hemisphere(wo, wi) || self.distrib.effectively_smooth() {
+            return Color::zero();
+        }
+
+        let cos_o = wo.z.abs();
+        let cos_i = wi.z.abs();
+        if cos_i == 0. || cos_o == 0. {
+            return Color::zero();
+        }
+
+        let wm = wi + wo;
+        if wm.magnitude_squared() == 0. {
+            return Color::zero();
+        }
+        let wm = wm.normalized();
+
+        let fr = microfacet::fresnel_complex(wo.dot(wm).abs(), self.eta, self.k);
+
+        fr * (self.distrib.d(wm) * self.distrib.g(wo, wi) / (4. * cos_i * cos_o))
+    }
+
+    fn sample_f(&self, wo: Vector3, _uc: f32, u: Point2D) -> Option<BsdfSample> {
+        if self.distrib.effectively_smooth() {
+            let wi = Vector3::new(-wo.x, -wo.y, wo.z);
+            let cos_i = wi.z.abs();
+
+            return Some(BsdfSample {
+                wi,
+                f: microfacet::fresnel_complex(cos_i, self.eta, self.k) / cos_i,
+                pdf: 1.,
+                specular: true,
+                eta: 1.
+            });
+        }
+
+        let wm = self.distrib.sample_wm(wo, u);
+        let wi = microfacet::reflect(wo, wm);
+        if !same_hemisphere(wo, wi) {
+            return None;
+        }
+
+        let pdf = self.distrib.pdf(wo, wm) / (4. * wo.dot(wm).abs());
+
+        let cos_o = wo.z.abs();
+        let cos_i = wi.z.abs();
+        if cos_i == 0. || cos_o == 0. {
+            return None;
+        }
+
+        let fr = microfacet::fresnel_complex(wo.dot(wm).abs(), self.eta, self.k);
+        let f = fr * (self.distrib.d(wm) * self.distrib.g(wo, wi) / (4. * cos_i * cos_o));
+
+        Some(BsdfSample { wi, f, pdf, specular: false, eta: 1. })
+    }
+
+    fn pdf(&self, wo: Vector3, wi: Vector3) -> f32 {
+        if !same_hemisphere(wo, wi) || self.distrib.effectively_smooth() {
+            return 0.;
+        }
+
+        let wm = wo + wi;
+        if wm.magnitude_squared() == 0. {
+            return 0.;
+        }
+        let wm = face_forward(wm.normalized(), Vector3::new(0., 0., 1.));
+
+        self.distrib.pdf(wo, wm) / (4. * wo.dot(wm).abs())
+    }
+
+    fn is_glossy(&self) -> bool {
+        self.distrib.is_glossy()
+    }
+}
+
+impl DielectricBxdf {
+    /// `eta` is the IOR of the side the shading normal points away from, over the side it points to.
+    pub fn new(distrib: TrowbridgeReitz, eta: f32) -> Self {
+        Self { distrib, eta }
+    }
+
+    /// Generalized half vector of a reflection or refraction pair, None for degenerate configurations.
+    fn half_vector(&self, wo: Vector3, wi: Vector3) -> Option<(Vector3, f32, bool)> {
+        let cos_o = wo.z;
+        let cos_i = wi.z;
+        let reflect = cos_i * cos_o > 0.;
+
+        let etap = if reflect {
+            1.
+        } else if cos_o > 0. {
+            self.eta
+        } else {
+            1. / self.eta
+        };
+
+        let wm = wi * etap + wo;
+        if cos_i == 0. || cos_o == 0. || wm.magnitude_squared() == 0. {
+            return None;
+        }
+        let wm = face_forward(wm.normalized(), Vector3::new(0., 0., 1.));
+
+        // microfacets seen from behind do not contribute
+        if wm.dot(wi) * cos_i < 0. || wm.dot(wo) * cos_o < 0. {
+            return None;
+        }
+
+        Some((wm, etap, reflect))
+    }
+}
+
+impl Bxdf for DielectricBxdf {
+    fn f(&self, wo: Vector3, wi: Vector3) -> Color {
+        if self.eta == 1. || self.distrib.effectively_smooth() {
+            return Color::zero();
+        }
+
+        let Some((wm, etap, reflect)) = self.half_vector(wo, wi) else {
+            return Color::zero();
+        };
+
+        let fr = microfacet::fresnel_dielectric(wo.dot(wm), self.eta);
+        let d = self.distrib.d(wm);
+        let g = self.distrib.g(wo, wi);
+
+        let value = if reflect {
+            d * g * fr / (4. * wi.z * wo.z).abs()
+        } else {
+            let denom = wi.dot(wm) + wo.dot(wm) / etap;
+            let denom = denom * denom * wi.z * wo.z;
+            // radiance is compressed when entering the denser medium
+            d * (1. - fr) * g * (wi.dot(wm) * wo.dot(wm) / denom).abs() / (etap * etap)
+        };
+
+        Color::new(value, value, value)
+    }
+
+    fn sample_f(&self, wo: Vector3, uc: f32, u: Point2D) -> Option<BsdfSample> {
+        if self.eta == 1. || self.distrib.effectively_smooth() {
+            let r = microfacet::fresnel_dielectric(wo.z, self.eta);
+            let t = 1. - r;
+
+            if uc < r / (r + t) {
+                let wi = Vector3::new(-wo.x, -wo.y, wo.z);
+                let value = r / wi.z.abs();
+
+                return Some(BsdfSample {
+                    wi,
+                    f: Color::new(value, value, value),
+                    pdf: r / (r + t),
+                    specular: true,
+                    eta: 1.
+                });
+            }
+
+            let (wi, etap) = microfacet::refract(wo, Vector3::new(0., 0., 1.), self.eta)?;
+            let value = t / wi.z.abs() / (etap * etap);
+
+            return Some(BsdfSample {
+                wi,
+                f: Color::new(value, value, value),
+                pdf: t / (r + t),
+                specular: true,
+                eta: etap
+            });
+        }
+
+        let wm = self.distrib.sample_wm(wo, u);
+        let r = microfacet::fresnel_dielectric(wo.dot(wm), self.eta);
+        let t = 1. - r;
+
+        if uc < r / (r + t) {
+            let wi = microfacet::reflect(wo, wm);
+            if !same_hemisphere(wo, wi) {
+                return None;
+            }
+
+            let pdf = self.distrib.pdf(wo, wm) / (4. * wo.dot(wm).abs()) * r / (r + t);
+            let value = self.distrib.d(wm) * self.distrib.g(wo, wi) * r / (4. * wi.z * wo.z);
+
+            return Some(BsdfSample { wi, f: Color::new(value, value, value), pdf, specular: false, eta: 1. });
+        }
+
+        let (wi, etap) = microfacet::refract(wo, wm, self.eta)?;
+        if same_hemisphere(wo, wi) || wi.z == 0. {
+            return None;
+        }
+
+        let denom = wi.dot(wm) + wo.dot(wm) / etap;
+        let denom = denom * denom;
+        let dwm_dwi = wi.dot(wm).abs() / denom;
+        let pdf = self.distrib.pdf(wo, wm) * dwm_dwi * t / (r + t);
+
+        let value = t * self.distrib.d(wm) * self.distrib.g(wo, wi)
+            * (wi.dot(wm) * wo.dot(wm) / (wi.z * wo.z * denom)).abs()
+            / (etap * etap);
+
+        Some(BsdfSample { wi, f: Color::new(value, value, value), pdf, specular: false, eta: etap })
+    }
+
+    fn pdf(&self, wo: Vector3, wi: Vector3) -> f32 {
+        if self.eta == 1. || self.distrib.effectively_smooth() {
+            return 0.;
+        }
+
+        let Some((wm, etap, reflect)) = self.half_vector(wo, wi) else {
+            return 0.;
+        };
+
+        let r = microfacet::fresnel_dielectric(wo.dot(wm), self.eta);
+        let t = 1. - r;
+
+        if reflect {
+            self.distrib.pdf(wo, wm) / (4. * wo.dot(wm).abs()) * r / (r + t)
+        } else {
+            let denom = wi.dot(wm) + wo.dot(wm) / etap;
+            let dwm_dwi = wi.dot(wm).abs() / (denom * denom);
+            self.distrib.pdf(wo, wm) * dwm_dwi * t / (r + t)
+        }
+    }
+
+    fn is_glossy(&self) -> bool {
+        self.eta == 1. || self.distrib.is_glossy()
+    }
+
+    fn eta(&self) -> f32 {
+        self.eta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::sampler::{RandomSampler, Sampler};
+
+    const N: usize = 256;
+
+    fn grid(n: usize) -> impl Iterator<Item = Point2D> {
+        (0..n * n).map(move |i| Point2D::new(((i % n) as f32 + 0.5) / n as f32, ((i / n) as f32 + 0.5) / n as f32))
+    }
+
+    /// Directional albedo of `bxdf` seen from `wo`, once from its own samples and once by uniform sampling.
+    fn albedo(bxdf: &dyn Bxdf, wo: Vector3) -> (f32, f32) {
+        let mut rng = RandomSampler::new(7, 0);
+        let mut sampled = 0.;
+        for u in grid(N) {
+            let Some(s) = bxdf.sample_f(wo, rng.next_1d(), u) else {
+                continue;
+            };
+
+            // the sample agrees with evaluating the BSDF and its pdf in the sampled direction
+            let (f, pdf) = (bxdf.f(wo, s.wi).x, bxdf.pdf(wo, s.wi));
+            assert!((s.pdf - pdf).abs() <= 1e-3 * pdf.max(1.), "pdf {} vs {}", s.pdf, pdf);
+            assert!((s.f.x - f).abs() <= 1e-3 * f.max(1.), "f {} vs {}", s.f.x, f);
+
+            sampled += (s.f.x * s.wi.z.abs() / s.pdf) as f64;
+        }
+
+        // uniform directions resolve narrow lobes poorly, they get more of them
+        let uniform: f64 = grid(4 * N).map(|u| {
+            let wi = sampling::uniform_sphere(u);
+            (bxdf.f(wo, wi).x * wi.z.abs() / sampling::uniform_sphere_pdf()) as f64
+        }).sum();
+
+        ((sampled / (N * N) as f64) as f32, (uniform / (16 * N * N) as f64) as f32)
+    }
+
+    fn directions() -> [Vector3; 3] {
+        [Vector3::new(0., 0., 1.), Vector3::new(0.5, 0., 0.866_025_4), Vector3::new(0.6, 0.6, 0.529_150_3)]
+    }
+
+    #[test]
+    fn ggx_conductor_conserves_energy() {
+        let white = ConductorBxdf::new(TrowbridgeReitz::new(0.3, 0.3), Color::new(0., 0., 0.), Color::new(1e4, 1e4, 1e4));
+        let anisotropic = ConductorBxdf::new(TrowbridgeReitz::new(0.2, 0.5), Color::new(0.2, 0.2, 0.2), Color::new(3.9, 3.9, 3.9));
+
+        for bxdf in [&white, &anisotropic] {
+            for wo in directions() {
+                let (sampled, uniform) = albedo(bxdf, wo);
+                assert!(sampled <= 1.001, "albedo {}", sampled);
+                assert!((sampled - uniform).abs() < 0.02, "sampled {} vs uniform {}", sampled, uniform);
+            }
+        }
+    }
+
+    #[test]
+    fn ggx_dielectric_conserves_energy() {
+        for bxdf in [DielectricBxdf::new(TrowbridgeReitz::new(0.4, 0.4), 1.5), DielectricBxdf::new(TrowbridgeReitz::new(0.5, 0.3), 1.33)] {
+            for wo in directions() {
+                let (sampled, uniform) = albedo(&bxdf, wo);
+                assert!(sampled <= 1.001, "albedo {}", sampled);
+                assert!((sampled - uniform).abs() < 0.02, "sampled {} vs uniform {}", sampled, uniform);
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::Arc;
+
+use crate::math::vector::{Onb, Point2D, Vector3};
+use crate::simulation::checkpoint::SceneHasher;
+use crate::simulation::hittable::{HitRecord, Hittable};
+use crate::simulation::material::Material;
+use crate::simulation::ray::Ray;
+use crate::simulation::spectrum::Wavelength;
+use crate::simulation::texture::Texture;
+
+/// Replaces the shading normal of a hit with a detailed one.
+pub trait NormalPerturbation: Send + Sync {
+    /// Returns the perturbed normal on the outward side of the surface.
+    fn perturb(&self, rec: &HitRecord, outward_normal: Vector3) -> Vector3;
+
+    /// Hashes what affects the rendered image, see `SceneHasher`.
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str(std::any::type_name::<Self>());
+    }
+}
+
+/// Tangent-space normal map: rgb in [0, 1] encodes xyz in [-1, 1], z along the surface normal.
+/// Image textures feeding it must be loaded as `ColorSpace::Raw`.
+pub struct NormalMap {
+    texture: Arc<dyn Texture>,
+    strength: f32
+}
+
+/// Height map, the normal is rebuilt from finite differences of the height along u and v.
+pub struct BumpMap {
+    height: Arc<dyn Texture>,
+    scale: f32,
+    delta: f32
+}
+
+/// Applies a `NormalPerturbation` to every hit of the wrapped object.
+pub struct Perturbed {
+    object: Box<dyn Hittable>,
+    detail: Box<dyn NormalPerturbation>
+}
+
+#[allow(dead_code)]
+impl NormalMap {
+    pub fn new(texture: Arc<dyn Texture>) -> Self {
+        Self { texture, strength: 1. }
+    }
+
+    /// Scales the tangent-space xy deviation, 0 disables the map.
+    pub fn with_strength(mut self, strength: f32) -> Self {
+        self.strength = strength;
+        self
+    }
+}
+
+impl NormalPerturbation for NormalMap {
+    fn perturb(&self, rec: &HitRecord, outward_normal: Vector3) -> Vector3 {
+        let c = self.texture.value(rec.uv(), rec.p()) * 2. - 1.;
+        let local = Vector3::new(c.x * self.strength, c.y * self.strength, c.z.max(0.));
+
+        if local.magnitude_squared() < 1e-12 {
+            return outward_normal;
+        }
+
+        Onb::from_wu(outward_normal, rec.dpdu()).to_world(local.normalized())
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("NormalMap");
+        self.texture.fingerprint(h);
+        h.write_f32(self.strength);
+    }
+}
+
+#[allow(dead_code)]
+impl BumpMap {
+    pub fn new(height: Arc<dyn Texture>, scale: f32) -> Self {
+        Self { height, scale, delta: 0.0005 }
+    }
+
+    /// Step in uv used for the finite differences.
+    pub fn with_delta(mut self, delta: f32) -> Self {
+        self.delta = delta.max(1e-6);
+        self
+    }
+
+    fn height(&self, uv: Point2D, rec: &HitRecord, du: f32, dv: f32) -> f32 {
+        let p = rec.p() + rec.dpdu() * du + rec.dpdv() * dv;
+        self.height.scalar(uv + Point2D::new(du, dv), p) * self.scale
+    }
+}
+
+impl NormalPerturbation for BumpMap {
+    fn perturb(&self, rec: &HitRecord, outward_normal: Vector3) -> Vector3 {
+        let uv = rec.uv();
+        let d = self.delta;
+
+        let h = self.height(uv, rec, 0., 0.);
+        let hu = self.height(uv, rec, d, 0.);
+        let hv = self.height(uv, rec, 0., d);
+
+        // displaced tangents, the change of the normal itself is ignored
+        let dpdu = rec.dpdu() + outward_normal * ((hu - h) / d);
+        let dpdv = rec.dpdv() + outward_normal * ((hv - h) / d);
+
+        let n = dpdu.cross(dpdv);
+        if n.magnitude_squared() < 1e-12 {
+            return outward_normal;
+        }
+
+        let n = n.normalized();
+        if n.dot(outward_normal) < 0. { -n } else { n }
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("BumpMap");
+        self.height.fingerprint(h);
+        h.write_f32(self.scale);
+        h.write_f32(self.delta);
+    }
+}
+
+#[allow(dead_code)]
+impl Perturbed {
+    pub fn new<T: Hittable + 'static, D: NormalPerturbation + 'static>(object: T, detail: D) -> Self {
+        Self { object: Box::new(object), detail: Box::new(detail) }
+    }
+}
+
+impl Hittable for Perturbed {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut rec = self.object.hit(ray, t_min, t_max)?;
+
+        let outward = if rec.front_face() { rec.geometric_normal() } else { -rec.geometric_normal() };
+        let n = self.detail.perturb(&rec, outward);
+
+        rec.set_shading_normal(if rec.front_face() { n } else { -n });
+
+        Some(rec)
+    }
+
+    fn area(&self) -> f32 {
+        self.object.area()
+    }
+
+    fn sample_surface(&self, u: Point2D, wavelength: Option<Wavelength>) -> Option<HitRecord> {
+        self.object.sample_surface(u, wavelength)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.object.is_emissive()
+    }
+
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        self.object.material()
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("Perturbed");
+        self.object.fingerprint(h);
+        self.detail.fingerprint(h);
+    }
+}
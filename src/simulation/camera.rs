@@ -1,34 +1,381 @@
-use crate::math::vector::{Point3D, Vector3};
+use std::f32::consts::{PI, TAU};
+
+use crate::math::vector::{Point2D, Point3D, Vector3};
+use crate::simulation::aperture::Aperture;
+use crate::simulation::checkpoint::SceneHasher;
 use crate::simulation::ray::Ray;
 
-pub struct Camera {
-    origin: Point3D,
-    lower_left_corner: Point3D,
+/// Projection from film coordinates to camera rays.
+pub trait CameraModel: Send + Sync {
+    /// Ray through film position (u, v) in [0, 1]^2, v = 0 is the top row.
+    /// None where the model does not cover the film, e.g. outside the fisheye image circle.
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray>;
+
+    /// Ray through (u, v) passing the lens at `lens` in [0, 1)^2, with the weight of the radiance
+    /// it brings back. Models without a lens ignore the sample and weigh all rays 1.
+    fn sample_ray(&self, u: f32, v: f32, _lens: Point2D) -> Option<(Ray, f32)> {
+        self.get_ray(u, v).map(|ray| (ray, 1.))
+    }
+
+    /// Connects scene point `p` to a point on the lens chosen with `u`, for light paths reaching the
+    /// camera. None where `p` is not seen, or for models that do not support it.
+    fn sample_importance(&self, _p: Point3D, _u: Point2D) -> Option<ImportanceSample> {
+        None
+    }
+
+    /// Density over directions of generating `ray` from its point on the lens, 0 where it misses
+    /// the film or the model does not support light paths.
+    fn pdf_direction(&self, _ray: &Ray) -> f32 {
+        0.
+    }
+
+    /// Position and orientation of the camera.
+    fn view(&self) -> View;
+
+    /// Hashes what affects the rendered image, see `SceneHasher`.
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str(std::any::type_name::<Self>());
+    }
+}
+
+/// Camera end of a connection from a scene point, see `CameraModel::sample_importance`.
+pub struct ImportanceSample {
+    /// Point on the lens, the connection runs from the scene point to here.
+    pub origin: Point3D,
+    /// Film position (u, v) the scene point is seen at.
+    pub film: Point2D,
+    /// Importance the camera gives to light from the scene point, divided by the density of choosing
+    /// the lens point as seen from the scene point.
+    pub weight: f32
+}
+
+/// Position and orientation shared by the camera models, an orthonormal basis looking along `forward`.
+#[derive(Copy, Clone)]
+pub struct View {
+    pub origin: Point3D,
+    pub right: Vector3,
+    pub up: Vector3,
+    pub forward: Vector3
+}
+
+impl Default for View {
+    /// At the world origin looking down -z, with +y up.
+    fn default() -> Self {
+        Self {
+            origin: Point3D::new(0., 0., 0.),
+            right: Vector3::new(1., 0., 0.),
+            up: Vector3::new(0., 1., 0.),
+            forward: Vector3::new(0., 0., -1.)
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl View {
+    pub fn look_at(from: Point3D, at: Point3D, vup: Vector3) -> Self {
+        let forward = (at - from).normalized();
+        let right = forward.cross(vup).normalized();
+        let up = right.cross(forward);
+
+        Self { origin: from, right, up, forward }
+    }
+
+    /// World direction of (x, y, z) given in the camera basis, y up and z forward.
+    pub fn direction(&self, x: f32, y: f32, z: f32) -> Vector3 {
+        self.right * x + self.up * y + self.forward * z
+    }
+
+    pub fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_vector(self.origin);
+        h.write_vector(self.right);
+        h.write_vector(self.up);
+        h.write_vector(self.forward);
+    }
+}
+
+/// Perspective projection onto a plane at unit distance. A pinhole by default, with a thin lens
+/// everything off the focus plane is blurred into the shape of the aperture.
+pub struct Perspective {
+    view: View,
+    aspect_ratio: f32,
+    vfov: f32,
+    lens_radius: f32,
+    focus_distance: f32,
+    aperture: Aperture,
+    top_left: Point3D,
     horizontal: Vector3,
-    vertical: Vector3,
+    vertical: Vector3
 }
 
-impl Camera {
+#[allow(dead_code)]
+impl Perspective {
+    /// 90 degree vertical field of view.
     pub fn new(aspect_ratio: f32) -> Self {
+        Self::with_fov(aspect_ratio, 90.)
+    }
 
-        let viewport_height: f32 = 2.0;
-        let viewport_width = viewport_height * aspect_ratio;
-        let focal_length = 1.0;
+    /// `vfov` is the vertical field of view in degrees.
+    pub fn with_fov(aspect_ratio: f32, vfov: f32) -> Self {
+        let mut camera = Self {
+            view: View::default(),
+            aspect_ratio,
+            vfov,
+            lens_radius: 0.,
+            focus_distance: 1.,
+            aperture: Aperture::Circular,
+            top_left: Point3D::zero(),
+            horizontal: Vector3::zero(),
+            vertical: Vector3::zero()
+        };
+        camera.update();
+        camera
+    }
 
-        let origin = Point3D::new(0., 0., 0.);
-        let horizontal= Vector3::right() * viewport_width;
-        let vertical = Vector3::up() * viewport_height;
-        let lower_left_corner = origin - horizontal * 0.5 - vertical * 0.5 - Vector3::forward() * focal_length;
+    pub fn with_view(mut self, view: View) -> Self {
+        self.view = view;
+        self.update();
+        self
+    }
 
-        Self {
-            origin,
-            horizontal,
-            vertical,
-            lower_left_corner
+    /// Thin lens of `lens_radius` focused on the plane `focus_distance` along the view direction.
+    pub fn with_thin_lens(mut self, lens_radius: f32, focus_distance: f32) -> Self {
+        self.lens_radius = lens_radius;
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    pub fn with_aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    fn update(&mut self) {
+        let viewport_height = 2. * (self.vfov.to_radians() * 0.5).tan();
+        let viewport_width = viewport_height * self.aspect_ratio;
+
+        self.horizontal = self.view.right * viewport_width;
+        self.vertical = -self.view.up * viewport_height;
+        self.top_left = self.view.origin - self.horizontal * 0.5 - self.vertical * 0.5 + self.view.forward;
+    }
+
+    /// Film position seen along unit direction `d` from `origin` on the lens.
+    fn film_position(&self, origin: Point3D, d: Vector3) -> Option<Point2D> {
+        let cos = d.dot(self.view.forward);
+        if cos <= 0. {
+            return None;
+        }
+
+        // the point in focus along the ray, projected back through the lens center to the image plane
+        let z = if self.lens_radius > 0. { self.focus_distance } else { 1. };
+        let focus = origin + d * (z / cos);
+        let q = self.view.origin + (focus - self.view.origin) / z - self.top_left;
+
+        let u = q.dot(self.horizontal) / self.horizontal.magnitude_squared();
+        let v = q.dot(self.vertical) / self.vertical.magnitude_squared();
+        if !(0. ..1.).contains(&u) || !(0. ..1.).contains(&v) {
+            return None;
+        }
+
+        Some(Point2D::new(u, v))
+    }
+
+    /// Area of the film on the image plane at unit distance.
+    fn film_area(&self) -> f32 {
+        self.horizontal.magnitude() * self.vertical.magnitude()
+    }
+}
+
+impl CameraModel for Perspective {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        Some(Ray::new(&self.view.origin, &(self.top_left + self.horizontal * u + self.vertical * v - self.view.origin)))
+    }
+
+    fn sample_ray(&self, u: f32, v: f32, lens: Point2D) -> Option<(Ray, f32)> {
+        if self.lens_radius <= 0. {
+            return self.get_ray(u, v).map(|ray| (ray, 1.));
+        }
+
+        // the pinhole direction reaches the image plane at unit distance, scale it to the focus plane
+        let focus = self.view.origin + (self.top_left + self.horizontal * u + self.vertical * v - self.view.origin) * self.focus_distance;
+
+        let p = self.aperture.sample(lens) * self.lens_radius;
+        let origin = self.view.origin + self.view.right * p.x + self.view.up * p.y;
+
+        Some((Ray::new(&origin, &(focus - origin)), 1.))
+    }
+
+    fn sample_importance(&self, p: Point3D, u: Point2D) -> Option<ImportanceSample> {
+        let origin = if self.lens_radius > 0. {
+            let a = self.aperture.sample(u) * self.lens_radius;
+            self.view.origin + self.view.right * a.x + self.view.up * a.y
+        } else {
+            self.view.origin
+        };
+
+        let d = p - origin;
+        let dist2 = d.magnitude_squared();
+        let d = d / dist2.sqrt();
+
+        let film = self.film_position(origin, d)?;
+
+        // importance is 1 / (A lens_area cos^4) over film area A, choosing the lens point has density
+        // dist^2 / (lens_area cos) over directions at p, the lens area cancels
+        let cos = d.dot(self.view.forward);
+        let weight = 1. / (self.film_area() * cos * cos * cos * dist2);
+
+        Some(ImportanceSample { origin, film, weight })
+    }
+
+    fn pdf_direction(&self, ray: &Ray) -> f32 {
+        let d = ray.direction().normalized();
+        if self.film_position(ray.origin(), d).is_none() {
+            return 0.;
         }
+
+        let cos = d.dot(self.view.forward);
+        1. / (self.film_area() * cos * cos * cos)
+    }
+
+    fn view(&self) -> View {
+        self.view
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("Perspective");
+        self.view.fingerprint(h);
+        h.write_f32(self.aspect_ratio);
+        h.write_f32(self.vfov);
+        h.write_f32(self.lens_radius);
+        h.write_f32(self.focus_distance);
+        self.aperture.fingerprint(h);
+    }
+}
+
+/// Parallel projection, rays leave the film plane along the view direction. Keeps parallel lines
+/// parallel, as in architectural elevations.
+pub struct Orthographic {
+    view: View,
+    width: f32,
+    height: f32
+}
+
+#[allow(dead_code)]
+impl Orthographic {
+    /// Film of `height` world units, as wide as the aspect ratio gives.
+    pub fn new(aspect_ratio: f32, height: f32) -> Self {
+        Self { view: View::default(), width: height * aspect_ratio, height }
+    }
+
+    pub fn with_view(mut self, view: View) -> Self {
+        self.view = view;
+        self
+    }
+}
+
+impl CameraModel for Orthographic {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        let origin = self.view.origin + self.view.right * ((u - 0.5) * self.width) - self.view.up * ((v - 0.5) * self.height);
+        Some(Ray::new(&origin, &self.view.forward))
+    }
+
+    fn view(&self) -> View {
+        self.view
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("Orthographic");
+        self.view.fingerprint(h);
+        h.write_f32(self.width);
+        h.write_f32(self.height);
+    }
+}
+
+/// Equidistant fisheye, the distance from the film center is proportional to the angle from the
+/// view direction. The image circle spans `fov` degrees across the shorter film side, film outside
+/// it stays black.
+pub struct Fisheye {
+    view: View,
+    aspect_ratio: f32,
+    fov: f32
+}
+
+#[allow(dead_code)]
+impl Fisheye {
+    pub fn new(aspect_ratio: f32, fov: f32) -> Self {
+        Self { view: View::default(), aspect_ratio, fov }
+    }
+
+    pub fn with_view(mut self, view: View) -> Self {
+        self.view = view;
+        self
+    }
+}
+
+impl CameraModel for Fisheye {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        // film position relative to the image circle of radius 1
+        let (sx, sy) = if self.aspect_ratio >= 1. { (self.aspect_ratio, 1.) } else { (1., 1. / self.aspect_ratio) };
+        let x = (2. * u - 1.) * sx;
+        let y = (1. - 2. * v) * sy;
+
+        let r = (x * x + y * y).sqrt();
+        if r > 1. {
+            return None;
+        }
+
+        let theta = r * self.fov.to_radians() * 0.5;
+        let phi = y.atan2(x);
+
+        let dir = self.view.direction(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+        Some(Ray::new(&self.view.origin, &dir))
+    }
+
+    fn view(&self) -> View {
+        self.view
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("Fisheye");
+        self.view.fingerprint(h);
+        h.write_f32(self.aspect_ratio);
+        h.write_f32(self.fov);
+    }
+}
+
+/// Full 360 by 180 degree panorama in latitude-longitude layout, as used for VR and environment
+/// maps. Longitude runs left to right with the view direction in the center, latitude top to bottom.
+/// The film should have a 2:1 aspect ratio.
+pub struct Equirectangular {
+    view: View
+}
+
+#[allow(dead_code)]
+impl Equirectangular {
+    pub fn new() -> Self {
+        Self { view: View::default() }
+    }
+
+    pub fn with_view(mut self, view: View) -> Self {
+        self.view = view;
+        self
+    }
+}
+
+impl CameraModel for Equirectangular {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        let longitude = (u - 0.5) * TAU;
+        let latitude = (0.5 - v) * PI;
+
+        let dir = self.view.direction(latitude.cos() * longitude.sin(), latitude.sin(), latitude.cos() * longitude.cos());
+        Some(Ray::new(&self.view.origin, &dir))
+    }
+
+    fn view(&self) -> View {
+        self.view
     }
 
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-        Ray::new(&self.origin, &(self.lower_left_corner + self.horizontal * u + self.vertical * v - self.origin))
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("Equirectangular");
+        self.view.fingerprint(h);
     }
 }
\ No newline at end of file
@@ -0,0 +1,301 @@
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::time::Duration;
+
+use crate::math::vector::Vector3;
+use crate::simulation::film::Film;
+use crate::simulation::sampler::RandomSampler;
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 2;
+
+/// Where and how often the engine saves its accumulated samples, and whether `simulate`
+/// continues from an existing checkpoint instead of starting over.
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    pub file_name: String,
+    pub interval: Duration,
+    pub resume: bool
+}
+
+#[allow(dead_code)]
+impl Checkpoint {
+    pub fn new(file_name: &str) -> Self {
+        Self {
+            file_name: String::from(file_name),
+            interval: Duration::from_secs(60),
+            resume: false
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+}
+
+/// FNV-1a, stable across runs, builds and targets so a checkpoint can be matched against the scene
+/// that wrote it. Integers are hashed as little-endian bytes, `usize` and `isize` widened to 64 bits.
+///
+/// Scene parts feed everything that affects the rendered image into it through their `fingerprint`
+/// methods. The trait defaults only tell types apart, implementors with parameters hash those too.
+pub struct SceneHasher {
+    state: u64
+}
+
+impl Default for SceneHasher {
+    fn default() -> Self {
+        Self { state: 0xcbf2_9ce4_8422_2325 }
+    }
+}
+
+impl Hasher for SceneHasher {
+    fn finish(&self) -> u64 {
+        self.state
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.state ^= b as u64;
+            self.state = self.state.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_u8(&mut self, v: u8) {
+        self.write(&[v]);
+    }
+
+    fn write_u16(&mut self, v: u16) {
+        self.write(&v.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, v: u32) {
+        self.write(&v.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, v: u64) {
+        self.write(&v.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, v: u128) {
+        self.write(&v.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, v: usize) {
+        self.write_u64(v as u64);
+    }
+
+    fn write_i8(&mut self, v: i8) {
+        self.write_u8(v as u8);
+    }
+
+    fn write_i16(&mut self, v: i16) {
+        self.write_u16(v as u16);
+    }
+
+    fn write_i32(&mut self, v: i32) {
+        self.write_u32(v as u32);
+    }
+
+    fn write_i64(&mut self, v: i64) {
+        self.write_u64(v as u64);
+    }
+
+    fn write_i128(&mut self, v: i128) {
+        self.write_u128(v as u128);
+    }
+
+    fn write_isize(&mut self, v: isize) {
+        self.write_i64(v as i64);
+    }
+}
+
+impl SceneHasher {
+    pub fn write_f32(&mut self, v: f32) {
+        self.write_u32(v.to_bits());
+    }
+
+    pub fn write_vector(&mut self, v: Vector3) {
+        self.write_f32(v.x);
+        self.write_f32(v.y);
+        self.write_f32(v.z);
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_usize(s.len());
+        self.write(s.as_bytes());
+    }
+}
+
+fn invalid(file_name: &str, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file_name, msg))
+}
+
+fn read_array<const N: usize>(i: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut b = [0u8; N];
+    i.read_exact(&mut b)?;
+    Ok(b)
+}
+
+/// Writes the film and per pixel sampler states. Goes through a temporary file so a kill
+/// during the write leaves the previous checkpoint intact.
+pub fn save(file_name: &str, scene_hash: u64, film: &Film, samplers: &[RandomSampler]) -> io::Result<()> {
+    let tmp = format!("{}.tmp", file_name);
+
+    {
+        let mut o = BufWriter::new(File::create(&tmp)?);
+
+        o.write_all(MAGIC)?;
+        o.write_all(&VERSION.to_le_bytes())?;
+        o.write_all(&scene_hash.to_le_bytes())?;
+        o.write_all(&film.width().to_le_bytes())?;
+        o.write_all(&film.height().to_le_bytes())?;
+
+        film.save(&mut o)?;
+
+        for sampler in samplers {
+            let (state, inc) = sampler.state();
+            o.write_all(&state.to_le_bytes())?;
+            o.write_all(&inc.to_le_bytes())?;
+        }
+
+        o.flush()?;
+    }
+
+    fs::rename(&tmp, file_name)
+}
+
+/// Restores what `save` wrote, refusing checkpoints of another scene or resolution.
+pub fn load(file_name: &str, scene_hash: u64, film: &mut Film, samplers: &mut [RandomSampler]) -> io::Result<()> {
+    let mut i = BufReader::new(File::open(file_name)?);
+
+    if &read_array::<4>(&mut i)? != MAGIC {
+        return Err(invalid(file_name, "not a checkpoint"));
+    }
+    if u32::from_le_bytes(read_array(&mut i)?) != VERSION {
+        return Err(invalid(file_name, "unsupported checkpoint version"));
+    }
+    if u64::from_le_bytes(read_array(&mut i)?) != scene_hash {
+        return Err(invalid(file_name, "checkpoint was written for a different scene"));
+    }
+
+    let width = i32::from_le_bytes(read_array(&mut i)?);
+    let height = i32::from_le_bytes(read_array(&mut i)?);
+    if width != film.width() || height != film.height() {
+        return Err(invalid(file_name, "checkpoint resolution does not match"));
+    }
+
+    film.load(&mut i)?;
+
+    for sampler in samplers.iter_mut() {
+        let state = u64::from_le_bytes(read_array(&mut i)?);
+        let inc = u64::from_le_bytes(read_array(&mut i)?);
+        *sampler = RandomSampler::from_state(state, inc);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::vector::{Color, Point2D};
+    use crate::simulation::filter::Filter;
+
+    fn temp_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("ray_tracing_{}_{}.ckpt", name, std::process::id()));
+        path.to_string_lossy().into_owned()
+    }
+
+    fn sampled_film() -> (Film, Vec<RandomSampler>) {
+        let mut film = Film::new(4, 3, Filter::default());
+        let mut samplers: Vec<RandomSampler> = (0..12).map(|i| RandomSampler::for_pixel(i % 4, i / 4, 7)).collect();
+
+        for (i, sampler) in samplers.iter_mut().enumerate() {
+            let (x, y) = ((i % 4) as i32, (i / 4) as i32);
+            let l = Color::new(i as f32, 0.5, 1. / (i + 1) as f32);
+            film.add_sample(Point2D::new(x as f32 + 0.5, y as f32 + 0.5), l);
+            film.record(x, y, l.x);
+            film.add_splat(Point2D::new(x as f32 + 0.25, y as f32 + 0.75), l * 2.);
+            sampler.next_u32();
+        }
+
+        (film, samplers)
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let file = temp_file("round_trip");
+        let (film, samplers) = sampled_film();
+        save(&file, 42, &film, &samplers).unwrap();
+
+        let mut loaded = Film::new(4, 3, Filter::default());
+        let mut loaded_samplers = vec![RandomSampler::new(0, 0); samplers.len()];
+        load(&file, 42, &mut loaded, &mut loaded_samplers).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        for y in 0..3 {
+            for x in 0..4 {
+                let (a, b) = (film.pixel(x, y), loaded.pixel(x, y));
+                assert_eq!([a.x, a.y, a.z], [b.x, b.y, b.z]);
+                let (a, b) = (film.splat(x, y), loaded.splat(x, y));
+                assert_eq!([a.x, a.y, a.z], [b.x, b.y, b.z]);
+                assert_eq!(film.stats(x, y).count(), loaded.stats(x, y).count());
+            }
+        }
+        for (a, b) in samplers.iter().zip(&loaded_samplers) {
+            assert_eq!(a.state(), b.state());
+        }
+    }
+
+    #[test]
+    fn load_rejects_other_scene() {
+        let file = temp_file("other_scene");
+        let (film, samplers) = sampled_film();
+        save(&file, 42, &film, &samplers).unwrap();
+
+        let mut loaded = Film::new(4, 3, Filter::default());
+        let mut loaded_samplers = samplers.clone();
+        let result = load(&file, 43, &mut loaded, &mut loaded_samplers);
+
+        let mut other_size = Film::new(3, 4, Filter::default());
+        let resized = load(&file, 42, &mut other_size, &mut loaded_samplers);
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(resized.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn hash_changes_with_settings() {
+        let hash = |spp: i32, name: &str| {
+            let mut h = SceneHasher::default();
+            h.write_i32(spp);
+            h.write_str(name);
+            h.finish()
+        };
+
+        assert_eq!(hash(16, "glass"), hash(16, "glass"));
+        assert_ne!(hash(16, "glass"), hash(17, "glass"));
+        assert_ne!(hash(16, "glass"), hash(16, "metal"));
+    }
+
+    #[test]
+    fn hash_is_fixed_width_little_endian() {
+        let mut a = SceneHasher::default();
+        a.write_usize(3);
+        a.write_i32(-2);
+
+        let mut b = SceneHasher::default();
+        b.write(&[3, 0, 0, 0, 0, 0, 0, 0]);
+        b.write(&[0xfe, 0xff, 0xff, 0xff]);
+
+        assert_eq!(a.finish(), b.finish());
+    }
+}
@@ -0,0 +1,168 @@
+use crate::math::vector::Color;
+
+/// Feature buffers guiding the denoiser, one entry per pixel of the denoised image.
+pub struct Guides {
+    pub albedo: Vec<Color>,
+    pub normal: Vec<Color>,
+    pub depth: Vec<f32>
+}
+
+/// Edge-avoiding À-trous wavelet filter (Dammertz et al. 2010). Every iteration blurs with a
+/// 5x5 B3 spline whose taps are spread twice as far apart as in the previous one, weighted down
+/// across differences in color, normal, depth and albedo. Lighting is filtered with the albedo
+/// divided out so textures stay sharp.
+#[derive(Copy, Clone, Debug)]
+pub struct Denoiser {
+    pub iterations: i32,
+    /// Tolerated color difference, halved every iteration.
+    pub sigma_color: f32,
+    pub sigma_normal: f32,
+    /// Tolerated depth difference, relative to the depth.
+    pub sigma_depth: f32,
+    pub sigma_albedo: f32
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_color: 1.,
+            sigma_normal: 0.3,
+            sigma_depth: 0.05,
+            sigma_albedo: 0.1
+        }
+    }
+}
+
+const KERNEL: [f32; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+/// Albedo to divide out of a pixel, 1 where there is none such as the background.
+fn demodulation(albedo: Color) -> Color {
+    const EPS: f32 = 1e-3;
+
+    if albedo.x + albedo.y + albedo.z <= EPS {
+        return Color::new(1., 1., 1.);
+    }
+
+    Color::new(albedo.x.max(EPS), albedo.y.max(EPS), albedo.z.max(EPS))
+}
+
+fn distance_squared(a: Color, b: Color) -> f32 {
+    (a - b).magnitude_squared()
+}
+
+#[allow(dead_code)]
+impl Denoiser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_iterations(mut self, iterations: i32) -> Self {
+        self.iterations = iterations.max(1);
+        self
+    }
+
+    pub fn with_sigma_color(mut self, sigma_color: f32) -> Self {
+        self.sigma_color = sigma_color;
+        self
+    }
+
+    /// Denoises a `width` x `height` linear image in place. The guides must cover the same pixels.
+    pub fn apply(&self, image: &mut [Color], width: i32, height: i32, guides: &Guides) {
+        let n = (width.max(0) * height.max(0)) as usize;
+        assert_eq!(image.len(), n, "image does not match {}x{}", width, height);
+        assert!(
+            guides.albedo.len() == n && guides.normal.len() == n && guides.depth.len() == n,
+            "guide buffers do not match the {}x{} image", width, height
+        );
+
+        let demod: Vec<Color> = guides.albedo.iter().map(|&a| demodulation(a)).collect();
+
+        let mut current: Vec<Color> = image.iter().zip(&demod).map(|(&c, &a)| c / a).collect();
+        let mut next = current.clone();
+
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            let sigma_color = self.sigma_color / (1 << iteration) as f32;
+
+            for y in 0..height {
+                for x in 0..width {
+                    let p = (x + y * width) as usize;
+
+                    let mut sum = Color::zero();
+                    let mut weight = 0.;
+
+                    for (j, ky) in KERNEL.iter().enumerate() {
+                        for (i, kx) in KERNEL.iter().enumerate() {
+                            let qx = x + (i as i32 - 2) * step;
+                            let qy = y + (j as i32 - 2) * step;
+                            if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                                continue;
+                            }
+
+                            let q = (qx + qy * width) as usize;
+                            let w = kx * ky * self.edge_weight(p, q, &current, sigma_color, guides);
+
+                            sum += current[q] * w;
+                            weight += w;
+                        }
+                    }
+
+                    next[p] = if weight > 0. { sum / weight } else { current[p] };
+                }
+            }
+
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        for ((c, filtered), a) in image.iter_mut().zip(&current).zip(&demod) {
+            *c = *filtered * *a;
+        }
+    }
+
+    fn edge_weight(&self, p: usize, q: usize, color: &[Color], sigma_color: f32, guides: &Guides) -> f32 {
+        // a zero sigma tolerates no difference at all rather than dividing 0 by 0
+        let (sigma_color, sigma_normal, sigma_albedo) = (sigma_color.max(f32::EPSILON), self.sigma_normal.max(f32::EPSILON), self.sigma_albedo.max(f32::EPSILON));
+
+        let dc = distance_squared(color[p], color[q]) / (sigma_color * sigma_color);
+        let dn = distance_squared(guides.normal[p], guides.normal[q]) / (sigma_normal * sigma_normal);
+        let da = distance_squared(guides.albedo[p], guides.albedo[q]) / (sigma_albedo * sigma_albedo);
+
+        let depth = guides.depth[p].abs().max(1e-3) * self.sigma_depth.max(f32::EPSILON);
+        let dd = (guides.depth[p] - guides.depth[q]).powi(2) / (depth * depth);
+
+        (-(dc + dn + da + dd)).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guides(n: usize) -> Guides {
+        Guides {
+            albedo: vec![Color::new(0.5, 0.5, 0.5); n],
+            normal: vec![Color::new(0., 0., 1.); n],
+            depth: vec![2.; n]
+        }
+    }
+
+    #[test]
+    fn zero_sigmas_keep_the_image_finite() {
+        let mut image: Vec<Color> = (0..16).map(|i| Color::new(i as f32, 1., 0.)).collect();
+        let denoiser = Denoiser { iterations: 2, sigma_color: 0., sigma_normal: 0., sigma_depth: 0., sigma_albedo: 0. };
+        denoiser.apply(&mut image, 4, 4, &guides(16));
+
+        // nothing is similar enough to blend with, every pixel keeps its value
+        for (i, c) in image.iter().enumerate() {
+            assert!((c.x - i as f32).abs() < 1e-4 && (c.y - 1.).abs() < 1e-4, "pixel {}", i);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "guide buffers")]
+    fn mismatched_guides_are_rejected() {
+        let mut image = vec![Color::zero(); 16];
+        Denoiser::default().apply(&mut image, 4, 4, &guides(12));
+    }
+}
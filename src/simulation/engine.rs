@@ -1,102 +1,1159 @@
 use core::slice;
 use std::fs::File;
+use std::hash::Hasher;
 use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::simulation::ray::Ray;
-use crate::simulation::hittable::{HittableList};
+use crate::simulation::checkpoint::{self, Checkpoint, SceneHasher};
+use crate::simulation::hittable::{Hittable, HittableList};
 use crate::simulation::result_image::{RGB256, ResultImage};
-use crate::math::vector::{Color, Vector2, Vector3};
-use crate::simulation::camera::Camera;
+use crate::math::vector::{Color, Point2D};
+use crate::math::sampling::Distribution1D;
+use crate::simulation::adaptive::{self, AdaptiveSampling, PixelStats};
+use crate::simulation::aov::{Aov, AovOutput, AovSample};
+use crate::simulation::denoise::{Denoiser, Guides};
+use crate::simulation::exr;
+use crate::simulation::exposure::Exposure;
+use crate::simulation::post::{Frame, PostStack};
+use crate::simulation::firefly::{FireflyStats, OutlierRejection, SampleClamp};
+use crate::simulation::camera::{CameraModel, Perspective};
+use crate::simulation::film::Film;
+use crate::simulation::light::LightList;
+use crate::simulation::mlt::{Mlt, MltSampler};
+use crate::simulation::filter::Filter;
+use crate::simulation::progress::{CancelToken, Progress, ProgressCallback};
+use crate::simulation::sampler::{RandomSampler, Sampler};
+use crate::simulation::spectrum::{self, Wavelength};
 
-use crate::math::noise::hash::{Vnoise};
+use crate::math::color::ColorSpace;
+
+/// Per sample state handed to the pixel function.
+#[allow(dead_code)]
+pub struct PixelContext<'a> {
+    pub x: i32,
+    pub y: i32,
+    pub u: f32,
+    pub v: f32,
+    pub sampler: &'a mut dyn Sampler,
+    /// Number of rays the pixel function traced, counted by the pixel function itself for statistics.
+    pub rays: u64,
+    /// AOVs of this sample, radiance AOVs are in the same units as the returned color.
+    pub aov: AovSample,
+    pub sample_clamp: Option<SampleClamp>,
+    /// Set when clamping limited any contribution of this sample, for statistics.
+    pub clamped: bool,
+    pub camera: &'a dyn CameraModel,
+    pub lights: &'a LightList,
+    /// Light this sample brings to other pixels, at film positions (u, v), e.g. from light paths
+    /// connected to the camera. Splats are in the same units as the returned color.
+    pub splats: Vec<(Point2D, Color)>
+}
+
+#[allow(dead_code)]
+impl PixelContext<'_> {
+    /// Applies the engine's sample clamping to light arriving after `bounce` bounces.
+    pub fn clamp_light(&mut self, bounce: i32, light: Color) -> Color {
+        match self.sample_clamp.and_then(|c| c.apply(bounce, light)) {
+            Some(clamped) => {
+                self.clamped = true;
+                clamped
+            }
+            None => light
+        }
+    }
+}
+
+/// Result of one camera sample, radiance is in the working space.
+struct CameraSample {
+    p: Point2D,
+    radiance: Color,
+    rays: u64,
+    aov: AovSample,
+    clamped: bool,
+    /// Light splatted to raster positions, in the working space.
+    splats: Vec<(Point2D, Color)>
+}
+
+/// What one state of an MLT chain brings to the image, at raster positions.
+struct MltContribution {
+    splats: Vec<(Point2D, Color)>,
+    /// Target function of the chain, the summed channel means of the splats.
+    importance: f32,
+    rays: u64,
+    clamped: bool
+}
+
+/// State of one MLT chain between rounds.
+struct Chain {
+    rng: RandomSampler,
+    sampler: MltSampler,
+    current: MltContribution
+}
+
+/// Mutations every MLT chain makes per round.
+const MLT_ROUND: u64 = 1024;
+
+/// Work done while sampling, summed over pixels and tiles.
+#[derive(Copy, Clone, Default)]
+struct SampleCounts {
+    samples: u64,
+    rays: u64,
+    clamped: u64
+}
+
+impl std::ops::AddAssign for SampleCounts {
+    fn add_assign(&mut self, o: Self) {
+        self.samples += o.samples;
+        self.rays += o.rays;
+        self.clamped += o.clamped;
+    }
+}
+
+const TILE_SIZE: i32 = 32;
+
+/// Pixels [x0, x1) x [y0, y1).
+struct Tile {
+    x0: i32,
+    y0: i32,
+    x1: i32,
+    y1: i32
+}
+
+/// Counters of one `simulate` call, shared across its passes.
+struct RunStats {
+    start: Instant,
+    last_checkpoint: Instant,
+    deadline: Option<Instant>,
+    counts: SampleCounts
+}
+
+impl RunStats {
+    fn progress(&self, pass: i32, passes: i32, tiles_done: usize, tiles_total: usize) -> Progress {
+        let elapsed = self.start.elapsed();
+        let seconds = elapsed.as_secs_f64().max(1e-6);
+
+        let mut progress = Progress {
+            pass,
+            passes,
+            tiles_done,
+            tiles_total,
+            samples: self.counts.samples,
+            rays: self.counts.rays,
+            clamped: self.counts.clamped,
+            elapsed,
+            samples_per_sec: self.counts.samples as f64 / seconds,
+            rays_per_sec: self.counts.rays as f64 / seconds,
+            eta: None
+        };
+
+        let fraction = progress.fraction();
+        if fraction > 0. {
+            progress.eta = Some(Duration::from_secs_f64(seconds * (1. - fraction) / fraction));
+        }
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            progress.eta = Some(progress.eta.map_or(remaining, |eta| eta.min(remaining)));
+        }
+
+        progress
+    }
+}
+
+/// Progressive rendering: every pass adds `samples_per_pass` samples to each unfinished pixel,
+/// then the output file is rewritten, or a numbered snapshot is written next to it.
+#[derive(Copy, Clone, Debug)]
+pub struct Progressive {
+    pub samples_per_pass: i32,
+    pub snapshots: bool
+}
+
+#[allow(dead_code)]
+impl Progressive {
+    pub fn new(samples_per_pass: i32) -> Self {
+        Self { samples_per_pass: samples_per_pass.max(1), snapshots: false }
+    }
+
+    pub fn with_snapshots(mut self, snapshots: bool) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+}
+
+/// Pixel rectangle [x0, x1) x [y0, y1) of the full frame to render, y going down from the top row.
+/// The camera keeps framing the full frame. With `crop` only the rectangle is written,
+/// otherwise the full-size image with the pixels outside left black.
+#[derive(Copy, Clone, Debug)]
+pub struct Region {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+    pub crop: bool
+}
+
+#[allow(dead_code)]
+impl Region {
+    pub fn new(x0: i32, y0: i32, x1: i32, y1: i32) -> Self {
+        Self { x0, y0, x1, y1, crop: false }
+    }
+
+    pub fn with_crop(mut self, crop: bool) -> Self {
+        self.crop = crop;
+        self
+    }
+
+    pub fn width(&self) -> i32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> i32 {
+        self.y1 - self.y0
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        self.x0 <= x && x < self.x1 && self.y0 <= y && y < self.y1
+    }
+}
+
+/// Appends `suffix` to the file name before its extension, optionally replacing the extension.
+/// `output.ppm` with suffix `_depth` and extension `exr` becomes `output_depth.exr`.
+fn derived_name(file_name: &str, suffix: &str, extension: Option<&str>) -> String {
+    let dot = file_name.rfind('.').filter(|&dot| !file_name[dot..].contains(['/', '\\']));
+
+    let (stem, current) = match dot {
+        Some(dot) => (&file_name[..dot], &file_name[dot + 1..]),
+        None => (file_name, "")
+    };
+
+    match extension.unwrap_or(current) {
+        "" => format!("{}{}", stem, suffix),
+        extension => format!("{}{}.{}", stem, suffix, extension)
+    }
+}
+
+/// `output.ppm` becomes `output_0003.ppm` for pass 3.
+fn snapshot_name(file_name: &str, pass: i32) -> String {
+    derived_name(file_name, &format!("_{:04}", pass), None)
+}
 
 pub struct Engine<F> 
 where 
-    F: Fn(&Ray, &HittableList, i32, i32, f32, f32, i32) -> Color,
+    F: Fn(&Ray, &HittableList, &mut PixelContext, i32) -> Color + Sync,
 {
     image: ResultImage,
+    film: Mutex<Film>,
     simulate: F,
     export_file_name: String,
     world: HittableList,
-    camera: Camera,
+    lights: LightList,
+    camera: Box<dyn CameraModel>,
     sample_per_pixel: i32,
-    trace: i32
+    trace: i32,
+    /// Space scene colors are given in, shading happens in and the film is kept in.
+    working_space: ColorSpace,
+    spectral: bool,
+    adaptive: Option<AdaptiveSampling>,
+    progressive: Option<Progressive>,
+    mlt: Option<Mlt>,
+    /// Factor turning splat sums into pixel values after an MLT render.
+    mlt_scale: Option<f32>,
+    /// Mutations made by the last MLT render.
+    mutations: u64,
+    heatmap_file: Option<String>,
+    checkpoint: Option<Checkpoint>,
+    samplers: Mutex<Vec<RandomSampler>>,
+    threads: usize,
+    progress: Option<ProgressCallback>,
+    time_limit: Option<Duration>,
+    region: Option<Region>,
+    aov_output: AovOutput,
+    aov_outputs: Vec<Aov>,
+    denoiser: Option<Denoiser>,
+    sample_clamp: Option<SampleClamp>,
+    outlier_rejection: Option<OutlierRejection>,
+    firefly_stats: FireflyStats,
+    exposure: Option<Exposure>,
+    exposure_multiplier: f32,
+    post: PostStack,
+    cancel: CancelToken
 }
 
 impl<F> Engine<F> 
 where 
-    F: Fn(&Ray, &HittableList, i32, i32, f32, f32, i32) -> Color,
+    F: Fn(&Ray, &HittableList, &mut PixelContext, i32) -> Color + Sync,
 {
     pub fn new(file_name: &str, width_resolution: i32, aspect_ratio: f32, simulate: F, sample_per_pixel: i32, trace: i32) -> Self {
         let width = width_resolution;
         let height = ((width_resolution as f32) / aspect_ratio) as i32;
         Self {
             image: ResultImage::new(width, height),
+            film: Mutex::new(Film::new(width, height, Filter::default())),
             simulate,
             export_file_name: String::from(file_name),
             world: HittableList::default(),
-            camera: Camera::new(aspect_ratio),
+            lights: LightList::default(),
+            camera: Box::new(Perspective::new(aspect_ratio)),
             sample_per_pixel,
-            trace
+            trace,
+            working_space: ColorSpace::LinearSrgb,
+            spectral: false,
+            adaptive: None,
+            progressive: None,
+            mlt: None,
+            mlt_scale: None,
+            mutations: 0,
+            heatmap_file: None,
+            checkpoint: None,
+            samplers: Mutex::new(Vec::new()),
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            progress: None,
+            time_limit: None,
+            region: None,
+            aov_output: AovOutput::MultiChannel,
+            aov_outputs: Vec::new(),
+            denoiser: None,
+            sample_clamp: None,
+            outlier_rejection: None,
+            firefly_stats: FireflyStats::default(),
+            exposure: None,
+            exposure_multiplier: 1.,
+            post: PostStack::new(),
+            cancel: CancelToken::new()
         }
     }
 
+    /// Space scene colors are given in and shading happens in. Display spaces map to their linear version.
+    /// Image textures of the scene must be loaded with the same working space.
+    pub fn set_working_space(&mut self, space: ColorSpace) {
+        self.working_space = space.linear();
+    }
+
+    /// Projection the camera rays are generated with, a 90 degree perspective by default.
+    pub fn set_camera<C: CameraModel + 'static>(&mut self, camera: C) {
+        self.camera = Box::new(camera);
+    }
+
+    /// Reconstruction filter used to splat samples onto the film, resets accumulated samples.
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.rebuild_film(filter);
+    }
+
+    /// AOVs to accumulate and write as EXR next to the output image when rendering, resets accumulated samples.
+    pub fn set_aovs(&mut self, aovs: &[Aov], output: AovOutput) {
+        self.aov_outputs = aovs.to_vec();
+        self.aov_output = output;
+
+        let filter = self.film.get_mut().unwrap().filter();
+        self.rebuild_film(filter);
+    }
+
+    /// Denoises the image before it is converted for display. Accumulates the albedo, normal and
+    /// depth AOVs it is guided by, which resets accumulated samples.
+    pub fn set_denoiser(&mut self, denoiser: Option<Denoiser>) {
+        self.denoiser = denoiser;
+
+        let filter = self.film.get_mut().unwrap().filter();
+        self.rebuild_film(filter);
+    }
+
+    /// Limits the radiance a single sample may carry, reported in `firefly_stats`. The pixel function
+    /// applies it through `PixelContext::clamp_light`.
+    pub fn set_sample_clamp(&mut self, clamp: Option<SampleClamp>) {
+        self.sample_clamp = clamp;
+    }
+
+    /// Filters outlier pixels out of the film before it is denoised and converted for display.
+    pub fn set_outlier_rejection(&mut self, rejection: Option<OutlierRejection>) {
+        self.outlier_rejection = rejection;
+    }
+
+    /// Scales the denoised image before post-processing. The AOV files stay as rendered.
+    pub fn set_exposure(&mut self, exposure: Option<Exposure>) {
+        self.exposure = exposure;
+        self.exposure_multiplier = 1.;
+    }
+
+    /// Multiplier the image was last developed with, the one auto exposure metered.
+    pub fn exposure_multiplier(&self) -> f32 {
+        self.exposure_multiplier
+    }
+
+    /// Effects applied to the linear image after denoising, before it is converted for display.
+    pub fn set_post(&mut self, post: PostStack) {
+        self.post = post;
+    }
+
+    pub fn firefly_stats(&self) -> FireflyStats {
+        self.firefly_stats
+    }
+
+    /// New empty film holding the AOVs written out and the ones post-processing needs.
+    fn rebuild_film(&mut self, filter: Filter) {
+        let mut aovs = self.aov_outputs.clone();
+        if self.denoiser.is_some() {
+            for guide in [Aov::Albedo, Aov::Normal, Aov::Depth] {
+                if !aovs.contains(&guide) {
+                    aovs.push(guide);
+                }
+            }
+        }
+
+        self.film = Mutex::new(Film::new(self.image.width(), self.image.height(), filter).with_aovs(&aovs));
+    }
+
+    /// Traces one wavelength per path instead of RGB, the scene itself is unchanged.
+    pub fn set_spectral(&mut self, spectral: bool) {
+        self.spectral = spectral;
+    }
+
+    /// Replaces the fixed `sample_per_pixel` count with a per-pixel budget driven by variance.
+    pub fn set_adaptive(&mut self, adaptive: Option<AdaptiveSampling>) {
+        self.adaptive = adaptive;
+    }
+
+    /// Renders in passes and writes the image after each one instead of only at the end.
+    pub fn set_progressive(&mut self, progressive: Option<Progressive>) {
+        self.progressive = progressive;
+    }
+
+    /// Renders with Metropolis light transport instead of sampling pixel by pixel. The pixel function
+    /// is evaluated as before, on random numbers chosen by the Markov chains. MLT renders the whole
+    /// image only, `simulate` fails when adaptive sampling, progressive passes, checkpoints, a
+    /// region, AOVs, denoising or a heatmap are set as well.
+    pub fn set_mlt(&mut self, mlt: Option<Mlt>) {
+        self.mlt = mlt;
+    }
+
+    /// Periodically saves the accumulated samples so `simulate` can resume an interrupted render.
+    pub fn set_checkpoint(&mut self, checkpoint: Option<Checkpoint>) {
+        self.checkpoint = checkpoint;
+    }
+
+    /// Wall clock budget for `simulate`. Samples are added over the whole image one per pixel at a time
+    /// until it runs out. Only adaptive sampling still stops pixels at their maximum count.
+    pub fn set_time_limit(&mut self, time_limit: Option<Duration>) {
+        self.time_limit = time_limit;
+    }
+
+    /// Renders only a rectangle of the frame, see `Region`.
+    pub fn set_region(&mut self, region: Option<Region>) {
+        self.region = region;
+    }
+
+    /// Fails with the settings MLT renders do not support, see `set_mlt`.
+    fn check_mlt(&self) -> io::Result<()> {
+        let unsupported: Vec<&str> = [
+            (self.adaptive.is_some(), "adaptive sampling"),
+            (self.progressive.is_some(), "progressive passes"),
+            (self.checkpoint.is_some(), "checkpoints"),
+            (self.region.is_some(), "render regions"),
+            (!self.aov_outputs.is_empty(), "AOVs"),
+            (self.denoiser.is_some(), "denoising"),
+            (self.heatmap_file.is_some(), "heatmaps")
+        ]
+        .iter()
+        .filter(|&&(set, _)| set)
+        .map(|&(_, name)| name)
+        .collect();
+
+        if unsupported.is_empty() {
+            return Ok(());
+        }
+
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("MLT renders do not support {}", unsupported.join(", "))))
+    }
+
+    /// Requested region clamped to the image, the full frame when there is none.
+    fn render_region(&self) -> Region {
+        let width = self.image.width();
+        let height = self.image.height();
+
+        match self.region {
+            Some(r) => {
+                let x0 = r.x0.clamp(0, width);
+                let y0 = r.y0.clamp(0, height);
+                Region { x0, y0, x1: r.x1.clamp(x0, width), y1: r.y1.clamp(y0, height), crop: r.crop }
+            }
+            None => Region::new(0, 0, width, height)
+        }
+    }
+
+    /// Number of worker threads, defaults to the available parallelism.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Maximum number of rays per path handed to the pixel function.
+    pub fn trace(&self) -> i32 {
+        self.trace
+    }
+
+    /// Called on the thread running `simulate` after every finished tile.
+    pub fn set_progress<P: Fn(&Progress) + Send + Sync + 'static>(&mut self, progress: P) {
+        self.progress = Some(Box::new(progress));
+    }
+
+    /// Token stopping `simulate` early when cancelled, from any thread. `render` then writes the partial image.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Also writes an image of the number of samples each pixel took when rendering.
+    pub fn set_heatmap(&mut self, file_name: Option<&str>) {
+        self.heatmap_file = file_name.map(String::from);
+    }
+
     pub fn world(&mut self) -> &mut HittableList
     {
         &mut self.world
     }
 
-    fn execute_pixel(&self, ray: &Ray, world: &HittableList, x: i32, y: i32, u: f32, v: f32, ray_step: i32) -> Color {
-        (self.simulate)(ray, world, x, y, u, v, ray_step)
+    fn execute_pixel(&self, ray: &Ray, world: &HittableList, ctx: &mut PixelContext, ray_step: i32) -> Color {
+        (self.simulate)(ray, world, ctx, ray_step)
+    }
+
+    fn pixel_context<'a>(&'a self, x: i32, y: i32, u: f32, v: f32, sampler: &'a mut dyn Sampler) -> PixelContext<'a> {
+        PixelContext {
+            x,
+            y,
+            u,
+            v,
+            sampler,
+            rays: 0,
+            aov: AovSample::default(),
+            sample_clamp: self.sample_clamp,
+            clamped: false,
+            camera: self.camera.as_ref(),
+            lights: &self.lights,
+            splats: Vec::new()
+        }
+    }
+
+    /// Traces one camera sample through pixel (x, y).
+    fn trace_sample(&self, x: i32, y: i32, sampler: &mut RandomSampler) -> CameraSample {
+        let jitter = sampler.next_2d();
+        self.trace_camera(Point2D::new(x as f32 + jitter.x, y as f32 + jitter.y), sampler)
     }
 
-    pub fn simulate(&mut self) {
+    /// Traces one camera sample through raster position `p`.
+    fn trace_camera(&self, p: Point2D, sampler: &mut dyn Sampler) -> CameraSample {
+        let (x, y) = (p.x.floor() as i32, p.y.floor() as i32);
+
+        let u: f32 = p.x / self.image.width() as f32;
+        let v: f32 = p.y / self.image.height() as f32;
+
+        let lens = sampler.next_2d();
+        let Some((ray, weight)) = self.camera.sample_ray(u, v, lens) else {
+            return CameraSample { p, radiance: Color::zero(), rays: 0, aov: AovSample::default(), clamped: false, splats: Vec::new() };
+        };
+
+        let (width, height) = (self.image.width() as f32, self.image.height() as f32);
+        let raster = |film: Point2D| Point2D::new(film.x * width, film.y * height);
+
+        let mut sample = if self.spectral {
+            let (lambda, pdf) = spectrum::sample_visible(sampler.next_1d());
+            let ray = ray.with_wavelength(Some(Wavelength { lambda, working_space: self.working_space }));
+
+            let mut ctx = self.pixel_context(x, y, u, v, sampler);
+            let l = self.execute_pixel(&ray, &self.world, &mut ctx, self.trace);
+
+            let to_working = |l: Color| self.working_space.from_xyz(spectrum::sample_to_xyz(lambda, pdf, (l.x + l.y + l.z) / 3.));
+            ctx.aov.map_radiance(to_working);
+            let splats = ctx.splats.iter().map(|&(film, l)| (raster(film), to_working(l))).collect();
+
+            CameraSample { p, radiance: to_working(l), rays: ctx.rays, aov: ctx.aov, clamped: ctx.clamped, splats }
+        } else {
+            let mut ctx = self.pixel_context(x, y, u, v, sampler);
+            let radiance = self.execute_pixel(&ray, &self.world, &mut ctx, self.trace);
+            let splats = ctx.splats.iter().map(|&(film, l)| (raster(film), l)).collect();
+            CameraSample { p, radiance, rays: ctx.rays, aov: ctx.aov, clamped: ctx.clamped, splats }
+        };
+
+        sample.radiance *= weight;
+        sample.aov.map_radiance(|l| l * weight);
+        sample
+    }
+
+    /// Hash of everything that decides what a sample contributes: resolution, camera, world and the
+    /// shading settings. The sample budget is left out so a resumed render may take more samples.
+    /// The pixel function itself can not be hashed, changing it is not detected.
+    fn scene_hash(&self) -> u64 {
+        let mut h = SceneHasher::default();
+
+        h.write_i32(self.image.width());
+        h.write_i32(self.image.height());
+        h.write_i32(self.trace);
+        h.write_u8(self.spectral as u8);
+        h.write_u8(self.working_space as u8);
+        {
+            let film = self.film.lock().unwrap();
+            film.filter().fingerprint(&mut h);
+            for &aov in film.aovs() {
+                h.write_u8(aov as u8);
+            }
+        }
+        if let Some(c) = self.sample_clamp {
+            c.fingerprint(&mut h);
+        }
+        self.camera.fingerprint(&mut h);
+        self.world.fingerprint(&mut h);
+
+        h.finish()
+    }
+
+    fn save_checkpoint(&self, scene_hash: u64) -> io::Result<()> {
+        let Some(c) = &self.checkpoint else {
+            return Ok(());
+        };
+
+        let film = self.film.lock().unwrap();
+        let samplers = self.samplers.lock().unwrap();
+        checkpoint::save(&c.file_name, scene_hash, &film, &samplers)
+    }
+
+    /// Fails when resuming from a missing checkpoint or one written for another scene, or when a checkpoint can not be written.
+    /// A cancelled render returns normally with whatever was sampled until then.
+    pub fn simulate(&mut self) -> io::Result<()> {
+        if self.mlt.is_some() {
+            self.check_mlt()?;
+        }
 
         let width = self.image.width();
         let height = self.image.height();
+        let scene_hash = self.scene_hash();
 
-        let sample_scale = 1. / self.sample_per_pixel as f32;
+        self.lights = LightList::new(&self.world);
 
-        let resw = 1.0 / (self.image.width() - 1) as f32;
-        let resh = 1.0 / (self.image.height() - 1) as f32;
+        let film = self.film.get_mut().unwrap();
+        let samplers = self.samplers.get_mut().unwrap();
 
-        for y in 0..height {
-            for x in 0..width {
-                let mut pixel_color = Color::new(0., 0., 0.);
+        film.clear();
+        *samplers = (0..height)
+            .flat_map(|y| (0..width).map(move |x| RandomSampler::for_pixel(x, y, 0)))
+            .collect();
+
+        let mut run = RunStats {
+            start: Instant::now(),
+            last_checkpoint: Instant::now(),
+            deadline: self.time_limit.map(|limit| Instant::now() + limit),
+            counts: SampleCounts::default()
+        };
+
+        self.mlt_scale = None;
+        if let Some(mlt) = self.mlt {
+            self.mlt_scale = Some(self.simulate_mlt(mlt, &mut run));
+            self.mutations = run.counts.samples;
+            self.firefly_stats.samples = run.counts.samples;
+            self.firefly_stats.clamped_samples = run.counts.clamped;
+
+            self.develop();
+            return Ok(());
+        }
+
+        if let Some(c) = self.checkpoint.as_ref().filter(|c| c.resume) {
+            checkpoint::load(&c.file_name, scene_hash, self.film.get_mut().unwrap(), self.samplers.get_mut().unwrap())?;
+        }
+
+        // a time limit spreads samples over the image in passes of one sample, so stopping leaves it evenly sampled
+        let samples_per_pass = match (self.progressive, self.time_limit) {
+            (Some(progressive), _) => Some(progressive.samples_per_pass),
+            (None, Some(_)) => Some(1),
+            (None, None) => None
+        };
+
+        match samples_per_pass {
+            None => {
+                self.simulate_pass(i32::MAX, 1, 1, scene_hash, &mut run)?;
+            }
+            Some(samples_per_pass) => {
+                // 0 passes for renders going on until the time limit
+                let (_, max_samples, _) = self.sample_budget();
+                let passes = if max_samples == i32::MAX { 0 } else { (max_samples + samples_per_pass - 1) / samples_per_pass };
 
-                for s in 0..self.sample_per_pixel {
-                    let u: f32 = (x as f32 + (Vnoise::rand21(Vector2::new(s as f32, 0.)) - 0.5) * 2.0) * resw;
-                    let v: f32 = (y as f32 + (Vnoise::rand21(Vector2::new(0., s as f32)) - 0.5) * 2.0) * resh;
+                let mut pass = 0;
+                while !self.stopped(run.deadline) && self.simulate_pass(samples_per_pass, pass + 1, passes, scene_hash, &mut run)? > 0 {
+                    pass += 1;
 
-                    let ray: Ray = self.camera.get_ray(u, v);
+                    let Some(progressive) = self.progressive else {
+                        continue;
+                    };
+                    self.develop();
 
-                    pixel_color += self.execute_pixel(&ray, &self.world, x, y, u, v, self.trace);
+                    let file_name = if progressive.snapshots {
+                        snapshot_name(&self.export_file_name, pass)
+                    } else {
+                        self.export_file_name.clone()
+                    };
+
+                    if let Err(e) = self.render_to(&file_name) {
+                        eprintln!("[ERROR] Failed to write snapshot {}: {}", file_name, e);
+                    }
                 }
+            }
+        }
+
+        self.firefly_stats.samples = run.counts.samples;
+        self.firefly_stats.clamped_samples = run.counts.clamped;
+
+        self.develop();
+        self.save_checkpoint(scene_hash)?;
+
+        Ok(())
+    }
+
+    /// Whether workers should stop, because the render was cancelled or ran out of time.
+    fn stopped(&self, deadline: Option<Instant>) -> bool {
+        self.cancel.is_cancelled() || deadline.is_some_and(|d| Instant::now() >= d)
+    }
 
-                self.image.pixels[(x + y * width) as usize] = RGB256 {
-                    data: [
-                        (pixel_color.x * 255.999 * sample_scale) as u8,
-                        (pixel_color.y * 255.999 * sample_scale) as u8,
-                        (pixel_color.z * 255.999 * sample_scale) as u8
-                    ]
-                };
+    /// Mean number of samples taken per rendered pixel, e.g. what a time-limited render achieved.
+    /// For MLT renders the mutations per image pixel.
+    pub fn achieved_spp(&self) -> f32 {
+        if self.mlt_scale.is_some() {
+            return self.mutations as f32 / (self.image.width() * self.image.height()).max(1) as f32;
+        }
+
+        let region = self.render_region();
+        let film = self.film.lock().unwrap();
+
+        let mut total = 0u64;
+        for y in region.y0..region.y1 {
+            for x in region.x0..region.x1 {
+                total += film.stats(x, y).count() as u64;
             }
         }
+
+        total as f32 / (region.width() * region.height()).max(1) as f32
     }
 
-    pub fn render(&self) -> io::Result<()> {
+    /// Image split into tiles of `TILE_SIZE` pixels, rendered independently by the workers.
+    fn tiles(&self) -> Vec<Tile> {
+        let region = self.render_region();
 
-        let file = File::create(self.export_file_name.as_str())?;
-        let mut o = std::io::BufWriter::new(file);
+        let mut tiles = Vec::new();
+        for y0 in (region.y0..region.y1).step_by(TILE_SIZE as usize) {
+            for x0 in (region.x0..region.x1).step_by(TILE_SIZE as usize) {
+                tiles.push(Tile { x0, y0, x1: (x0 + TILE_SIZE).min(region.x1), y1: (y0 + TILE_SIZE).min(region.y1) });
+            }
+        }
+
+        tiles
+    }
+
+    /// Takes up to `budget` more samples in every pixel that still needs them, returns the number of samples taken.
+    /// Tiles are rendered by `threads` workers, this thread reports progress and saves checkpoints once their interval has passed.
+    fn simulate_pass(&self, budget: i32, pass: i32, passes: i32, scene_hash: u64, run: &mut RunStats) -> io::Result<u64> {
+        let tiles = self.tiles();
+        let next = AtomicUsize::new(0);
+        let (tx, rx) = mpsc::channel::<SampleCounts>();
+
+        let deadline = run.deadline;
+
+        let mut taken = 0;
+        let mut result = Ok(());
+
+        thread::scope(|s| {
+            for _ in 0..self.threads {
+                let tx = tx.clone();
+                let tiles = &tiles;
+                let next = &next;
+
+                s.spawn(move || {
+                    while !self.stopped(deadline) {
+                        let Some(tile) = tiles.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                            break;
+                        };
+
+                        if tx.send(self.simulate_tile(tile, budget, deadline)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(tx);
+
+            let mut tiles_done = 0;
+            for counts in rx {
+                tiles_done += 1;
+                taken += counts.samples;
+                run.counts += counts;
+
+                if let Some(report) = &self.progress {
+                    report(&run.progress(pass, passes, tiles_done, tiles.len()));
+                }
+
+                if result.is_ok() && self.checkpoint.as_ref().is_some_and(|c| run.last_checkpoint.elapsed() >= c.interval) {
+                    result = self.save_checkpoint(scene_hash);
+                    run.last_checkpoint = Instant::now();
+                }
+            }
+        });
+
+        result.map(|_| taken)
+    }
 
+    /// Renders one tile into its own film and merges it back, returns the number of samples and rays traced.
+    fn simulate_tile(&self, tile: &Tile, budget: i32, deadline: Option<Instant>) -> SampleCounts {
+        let width = self.image.width();
+
+        let mut film = self.film.lock().unwrap().tile(tile.x0, tile.y0, tile.x1, tile.y1);
+        let mut samplers = Vec::with_capacity(((tile.x1 - tile.x0) * (tile.y1 - tile.y0)) as usize);
         {
-            let img = &self.image;
-            write!(o, "P6\n{} {}\n255\n", img.width(), img.height())?;
+            let all = self.samplers.lock().unwrap();
+            for y in tile.y0..tile.y1 {
+                for x in tile.x0..tile.x1 {
+                    samplers.push(all[(x + y * width) as usize]);
+                }
+            }
+        }
+
+        let mut counts = SampleCounts::default();
+        let mut splats = Vec::new();
+        let mut i = 0;
+
+        'tile: for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                if self.stopped(deadline) {
+                    break 'tile;
+                }
+
+                counts += self.simulate_pixel(&mut film, &mut splats, &mut samplers[i], x, y, budget);
+                i += 1;
+            }
+        }
+
+        let mut main = self.film.lock().unwrap();
+        let mut all = self.samplers.lock().unwrap();
+
+        main.merge(&film);
+        // splats land anywhere in the image, outside the tile film
+        for (p, l) in splats {
+            main.add_splat(p, l);
+        }
+
+        let mut i = 0;
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                all[(x + y * width) as usize] = samplers[i];
+                i += 1;
+            }
+        }
+
+        counts
+    }
+
+    /// Per pixel sample count bounds and batch size, fixed unless adaptive sampling is enabled.
+    /// A time limit without adaptive sampling lifts the maximum, samples are added until it runs out.
+    fn sample_budget(&self) -> (i32, i32, i32) {
+        match self.adaptive {
+            Some(a) => (a.min_samples, a.max_samples, a.batch),
+            None if self.time_limit.is_some() => (self.sample_per_pixel, i32::MAX, self.sample_per_pixel),
+            None => (self.sample_per_pixel, self.sample_per_pixel, self.sample_per_pixel)
+        }
+    }
+
+    /// Whether a pixel has reached its maximum count or, in adaptive mode, has converged.
+    fn pixel_done(&self, stats: PixelStats) -> bool {
+        let (min_samples, max_samples, _) = self.sample_budget();
+        let taken = stats.count() as i32;
+
+        match self.adaptive {
+            Some(a) if taken >= min_samples && stats.relative_error() < a.threshold => true,
+            _ => taken >= max_samples
+        }
+    }
+
+    /// Samples pixel (x, y) until it is done or `budget` samples were taken, returns the number of samples and rays.
+    /// Splats of the samples are collected in `splats`.
+    fn simulate_pixel(&self, film: &mut Film, splats: &mut Vec<(Point2D, Color)>, sampler: &mut RandomSampler, x: i32, y: i32, budget: i32) -> SampleCounts {
+        let working_space = self.working_space;
+
+        let (min_samples, max_samples, batch) = self.sample_budget();
+
+        let mut taken = 0;
+        let mut counts = SampleCounts::default();
+        while taken < budget && !self.pixel_done(film.stats(x, y)) {
+            let total = film.stats(x, y).count() as i32;
+            let count = if total < min_samples { min_samples - total } else { batch.min(max_samples - total) };
+            let count = count.min(budget - taken);
+
+            for _ in 0..count {
+                let sample = self.trace_sample(x, y, sampler);
+                film.add_sample(sample.p, sample.radiance);
+                film.record(x, y, working_space.luminance(sample.radiance));
+                film.add_aovs(x, y, &sample.aov);
+                splats.extend(sample.splats);
+                counts.rays += sample.rays;
+                counts.clamped += sample.clamped as u64;
+            }
+            taken += count;
+        }
+
+        counts.samples = taken as u64;
+        counts
+    }
+
+    /// Evaluates the pixel function on the current numbers of an MLT chain, the first two choosing
+    /// the film position.
+    fn mlt_contribution(&self, sampler: &mut MltSampler) -> MltContribution {
+        let film = sampler.next_2d();
+        let p = Point2D::new(film.x * self.image.width() as f32, film.y * self.image.height() as f32);
+        let sample = self.trace_camera(p, sampler);
+
+        let mut splats = sample.splats;
+        splats.push((sample.p, sample.radiance));
+
+        // the channel mean rather than luminance, single wavelengths of dark blue have almost none
+        let importance: f32 = splats.iter().map(|&(_, l)| (l.x.abs() + l.y.abs() + l.z.abs()) / 3.).sum();
+        // NaN is never proposed
+        let importance = if importance > 0. { importance } else { 0. };
+
+        MltContribution { splats, importance, rays: sample.rays, clamped: sample.clamped }
+    }
+
+    /// Runs the MLT bootstrap and chains, splatting into the film. Returns the factor turning splat
+    /// sums into pixel values: the mean bootstrap importance per mutation per pixel.
+    fn simulate_mlt(&self, mlt: Mlt, run: &mut RunStats) -> f32 {
+        let pixel_count = (self.image.width() * self.image.height()) as u64;
+
+        // bootstrap samples are the first states of chains seeded by their index
+        let mut weights = vec![0.; mlt.bootstrap];
+        let chunk = mlt.bootstrap.div_ceil(self.threads.max(1));
+        thread::scope(|s| {
+            let workers: Vec<_> = weights
+                .chunks_mut(chunk)
+                .enumerate()
+                .map(|(k, weights)| {
+                    s.spawn(move || {
+                        let mut rays = 0;
+                        for (i, w) in weights.iter_mut().enumerate() {
+                            let mut sampler = MltSampler::new((k * chunk + i) as u64, mlt.sigma, mlt.large_step_probability);
+                            let c = self.mlt_contribution(&mut sampler);
+                            *w = c.importance;
+                            rays += c.rays;
+                        }
+                        rays
+                    })
+                })
+                .collect();
+
+            for worker in workers {
+                run.counts.rays += worker.join().unwrap();
+            }
+        });
+
+        let b = weights.iter().map(|&w| w as f64).sum::<f64>() / mlt.bootstrap as f64;
+        if b <= 0. {
+            return 0.;
+        }
+        let starts = Distribution1D::new(&weights);
+
+        let total = mlt.mutations_per_pixel as u64 * pixel_count;
+        let chain_mutations = |chain: usize| total / mlt.chains as u64 + ((chain as u64) < total % mlt.chains as u64) as u64;
+        let rounds = chain_mutations(0).div_ceil(MLT_ROUND) as i32;
+
+        // every round advances all chains a little, so stopping early leaves all of them sampled alike
+        let chains: Vec<Mutex<Option<Chain>>> = (0..mlt.chains).map(|_| Mutex::new(None)).collect();
+        let deadline = run.deadline;
+
+        for round in 0..rounds {
+            if self.stopped(deadline) {
+                break;
+            }
+
+            let next = AtomicUsize::new(0);
+            let (tx, rx) = mpsc::channel::<SampleCounts>();
+
+            thread::scope(|s| {
+                for _ in 0..self.threads {
+                    let tx = tx.clone();
+                    let next = &next;
+                    let chains = &chains;
+                    let starts = &starts;
+
+                    s.spawn(move || {
+                        while !self.stopped(deadline) {
+                            let chain = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(state) = chains.get(chain) else {
+                                break;
+                            };
+
+                            let mutations = chain_mutations(chain).saturating_sub(round as u64 * MLT_ROUND).min(MLT_ROUND);
+                            let counts = self.advance_chain(mlt, chain, &mut state.lock().unwrap(), starts, mutations);
+                            if tx.send(counts).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                }
+                drop(tx);
+
+                let mut chains_done = 0;
+                for counts in rx {
+                    chains_done += 1;
+                    run.counts += counts;
+
+                    if let Some(report) = &self.progress {
+                        report(&run.progress(round + 1, rounds, chains_done, mlt.chains));
+                    }
+                }
+            });
         }
 
+        if run.counts.samples == 0 {
+            return 0.;
+        }
+
+        (b * pixel_count as f64 / run.counts.samples as f64) as f32
+    }
+
+    /// Makes `mutations` steps of a chain, starting it from a bootstrap sample chosen by importance
+    /// on its first round. Both the current and the proposed state are splatted, weighted by their
+    /// acceptance probability over their importance, so rejected proposals still count.
+    /// Returns the mutations made as samples.
+    fn advance_chain(&self, mlt: Mlt, index: usize, chain: &mut Option<Chain>, starts: &Distribution1D, mutations: u64) -> SampleCounts {
+        let mut counts = SampleCounts::default();
+
+        let chain = chain.get_or_insert_with(|| {
+            let mut rng = RandomSampler::new(index as u64, 0x636861696e);
+            // bootstrap samples without importance have pdf 0, rounding could still land on one
+            let start = loop {
+                let (_, pdf, start) = starts.sample(rng.next_1d());
+                if pdf > 0. {
+                    break start;
+                }
+            };
+
+            let mut sampler = MltSampler::new(start as u64, mlt.sigma, mlt.large_step_probability);
+            let current = self.mlt_contribution(&mut sampler);
+            counts.rays += current.rays;
+
+            Chain { rng, sampler, current }
+        });
+
+        let mut splats = Vec::new();
+        for _ in 0..mutations {
+            chain.sampler.start_iteration();
+            let proposed = self.mlt_contribution(&mut chain.sampler);
+            let current = &chain.current;
+
+            let accept = if current.importance > 0. { (proposed.importance / current.importance).min(1.) } else { 1. };
+            // a proposal without importance adds nothing, splatting it would divide 0 by 0
+            if accept > 0. && proposed.importance > 0. {
+                splats.extend(proposed.splats.iter().map(|&(p, l)| (p, l * (accept / proposed.importance))));
+            }
+            if accept < 1. {
+                splats.extend(current.splats.iter().map(|&(p, l)| (p, l * ((1. - accept) / current.importance))));
+            }
+
+            counts.samples += 1;
+            counts.rays += proposed.rays;
+            counts.clamped += proposed.clamped as u64;
+
+            if chain.rng.next_1d() < accept {
+                chain.current = proposed;
+                chain.sampler.accept();
+            } else {
+                chain.sampler.reject();
+            }
+        }
+
+        let mut film = self.film.lock().unwrap();
+        for (p, l) in splats {
+            film.add_splat(p, l);
+        }
+
+        counts
+    }
+
+    /// Factor turning splat sums into pixel values. Every sample in the region may splat anywhere in
+    /// the image of `pixel_count` pixels, so splats are averaged over the mean samples per pixel.
+    fn splat_scale(film: &Film, region: &Region, pixel_count: i32) -> f32 {
+        let mut total = 0u64;
+        for y in region.y0..region.y1 {
+            for x in region.x0..region.x1 {
+                total += film.stats(x, y).count() as u64;
+            }
+        }
+
+        if total == 0 {
+            return 0.;
+        }
+
+        pixel_count as f32 / total as f32
+    }
+
+    /// Converts the float film to the 8-bit display image.
+    /// Pixels outside the render region are left black, filtered samples spilling over its border are dropped.
+    fn develop(&mut self) {
+        let width = self.image.width();
+        let working_space = self.working_space;
+        let region = self.render_region();
+        let film = self.film.get_mut().unwrap();
+
+        // linear image of the region, post-processed before display conversion
+        let pixels = || (region.y0..region.y1).flat_map(|y| (region.x0..region.x1).map(move |x| (x, y)));
+        let splat_scale = self.mlt_scale.unwrap_or_else(|| Self::splat_scale(film, &region, width * self.image.height()));
+        let mut hdr: Vec<Color> = pixels().map(|(x, y)| film.pixel(x, y) + film.splat(x, y) * splat_scale).collect();
+
+        if let Some(rejection) = &self.outlier_rejection {
+            self.firefly_stats.rejected_pixels = rejection.apply(&mut hdr, region.width(), region.height(), working_space);
+        }
+
+        if let Some(denoiser) = &self.denoiser {
+            let guides = Guides {
+                albedo: pixels().map(|(x, y)| film.aov(x, y, Aov::Albedo)).collect(),
+                normal: pixels().map(|(x, y)| film.aov(x, y, Aov::Normal)).collect(),
+                depth: pixels().map(|(x, y)| film.aov(x, y, Aov::Depth).x).collect()
+            };
+            denoiser.apply(&mut hdr, region.width(), region.height(), &guides);
+        }
+
+        if let Some(exposure) = &self.exposure {
+            self.exposure_multiplier = exposure.multiplier(&hdr, working_space);
+            hdr.iter_mut().for_each(|c| *c *= self.exposure_multiplier);
+        }
+
+        let frame = Frame {
+            x0: region.x0,
+            y0: region.y0,
+            width: region.width(),
+            height: region.height(),
+            full_width: self.image.width(),
+            full_height: self.image.height(),
+            working_space
+        };
+        self.post.apply(&mut hdr, &frame);
+
+        for ((x, y), c) in pixels().zip(hdr) {
+            let display = ColorSpace::convert(c, working_space, ColorSpace::Srgb);
+
+            self.image.pixels[(x + y * width) as usize] = RGB256 {
+                data: [
+                    (display.x.clamp(0., 1.) * 255.999) as u8,
+                    (display.y.clamp(0., 1.) * 255.999) as u8,
+                    (display.z.clamp(0., 1.) * 255.999) as u8
+                ]
+            };
+        }
+    }
+
+    fn write_ppm(file_name: &str, width: i32, height: i32, pixels: &[RGB256]) -> io::Result<()> {
+
+        let file = File::create(file_name)?;
+        let mut o = std::io::BufWriter::new(file);
+
+        write!(o, "P6\n{} {}\n255\n", width, height)?;
+
         {
-            let pixels = &self.image.pixels;
-            
             let ptr = pixels.as_ptr() as *const u8;
             let len = pixels.len() * 3;
 
@@ -109,4 +1166,122 @@ where
 
         Ok(())
     }
+
+    /// Writes a full-size image, or only the render region when it is cropped.
+    fn write_output(&self, file_name: &str, pixels: &[RGB256]) -> io::Result<()> {
+        let width = self.image.width();
+        let region = self.render_region();
+
+        if !region.crop {
+            return Self::write_ppm(file_name, width, self.image.height(), pixels);
+        }
+
+        let mut cropped = Vec::with_capacity((region.width() * region.height()) as usize);
+        for y in region.y0..region.y1 {
+            cropped.extend_from_slice(&pixels[(region.x0 + y * width) as usize..(region.x1 + y * width) as usize]);
+        }
+
+        Self::write_ppm(file_name, region.width(), region.height(), &cropped)
+    }
+
+    fn render_to(&self, file_name: &str) -> io::Result<()> {
+        self.write_output(file_name, &self.image.pixels)
+    }
+
+    pub fn render(&self) -> io::Result<()> {
+        self.render_to(self.export_file_name.as_str())?;
+
+        if let Some(heatmap_file) = &self.heatmap_file {
+            self.render_heatmap(heatmap_file)?;
+        }
+
+        self.render_aovs()
+    }
+
+    /// Writes the AOVs, together with the linear beauty image when they go into one file.
+    fn render_aovs(&self) -> io::Result<()> {
+        let film = self.film.lock().unwrap();
+        if self.aov_outputs.is_empty() {
+            return Ok(());
+        }
+
+        let region = self.render_region();
+        let output = if region.crop { region } else { Region::new(0, 0, self.image.width(), self.image.height()) };
+        let splat_scale = self.mlt_scale.unwrap_or_else(|| Self::splat_scale(&film, &region, self.image.width() * self.image.height()));
+        let beauty = |x: i32, y: i32| film.pixel(x, y) + film.splat(x, y) * splat_scale;
+
+        let channel = |name: String, value: &dyn Fn(i32, i32) -> f32| {
+            let mut values = Vec::with_capacity((output.width() * output.height()) as usize);
+            for y in output.y0..output.y1 {
+                for x in output.x0..output.x1 {
+                    values.push(if region.contains(x, y) { value(x, y) } else { 0. });
+                }
+            }
+            exr::Channel { name, values }
+        };
+
+        let aov_channels = |aov: Aov, layer: bool| -> Vec<exr::Channel> {
+            aov.channels()
+                .iter()
+                .enumerate()
+                .map(|(k, suffix)| {
+                    let name = if layer { format!("{}.{}", aov.name(), suffix) } else { suffix.to_string() };
+                    channel(name, &|x, y| {
+                        let v = film.aov(x, y, aov);
+                        [v.x, v.y, v.z][k]
+                    })
+                })
+                .collect()
+        };
+
+        match self.aov_output {
+            AovOutput::MultiChannel => {
+                let mut channels = vec![
+                    channel(String::from("R"), &|x, y| beauty(x, y).x),
+                    channel(String::from("G"), &|x, y| beauty(x, y).y),
+                    channel(String::from("B"), &|x, y| beauty(x, y).z)
+                ];
+                for &aov in &self.aov_outputs {
+                    channels.extend(aov_channels(aov, true));
+                }
+
+                exr::write(&derived_name(&self.export_file_name, "", Some("exr")), output.width(), output.height(), &channels)
+            }
+            AovOutput::Separate => {
+                for &aov in &self.aov_outputs {
+                    let file_name = derived_name(&self.export_file_name, &format!("_{}", aov.name()), Some("exr"));
+                    exr::write(&file_name, output.width(), output.height(), &aov_channels(aov, false))?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn render_heatmap(&self, file_name: &str) -> io::Result<()> {
+        let width = self.image.width();
+        let height = self.image.height();
+
+        let film = self.film.lock().unwrap();
+
+        // renders going on until the time limit have no fixed maximum, scale to the busiest pixel
+        let (_, max_samples, _) = self.sample_budget();
+        let max = if max_samples == i32::MAX {
+            (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| film.stats(x, y).count()).max().unwrap_or(0)
+        } else {
+            max_samples as u32
+        };
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let c = adaptive::heat(film.stats(x, y).count(), max);
+                pixels.push(RGB256 {
+                    data: [(c.x * 255.999) as u8, (c.y * 255.999) as u8, (c.z * 255.999) as u8]
+                });
+            }
+        }
+
+        self.write_output(file_name, &pixels)
+    }
 }
\ No newline at end of file
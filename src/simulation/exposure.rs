@@ -0,0 +1,83 @@
+use crate::math::color::ColorSpace;
+use crate::math::vector::Color;
+
+/// Sensor sensitivity, shutter time in seconds and relative aperture of a physical camera.
+#[derive(Copy, Clone, Debug)]
+pub struct CameraSettings {
+    pub iso: f32,
+    pub shutter: f32,
+    pub f_stop: f32
+}
+
+#[allow(dead_code)]
+impl CameraSettings {
+    pub fn new(iso: f32, shutter: f32, f_stop: f32) -> Self {
+        Self { iso, shutter, f_stop }
+    }
+
+    /// Exposure value at ISO 100, higher for less light reaching the sensor.
+    pub fn ev100(&self) -> f32 {
+        (self.f_stop * self.f_stop / self.shutter * 100. / self.iso).log2()
+    }
+
+    /// Film multiplier, taking rendered luminance as cd/m^2. The brightest luminance that does not
+    /// clip is 1.2 * 2^EV100, from the saturation based sensitivity of ISO 12232 (Lagarde & de Rousiers 2014).
+    pub fn multiplier(&self) -> f32 {
+        1. / (1.2 * self.ev100().exp2())
+    }
+}
+
+/// How the film is scaled before post-processing and display conversion.
+#[derive(Copy, Clone, Debug)]
+pub enum Exposure {
+    Manual(f32),
+    Physical(CameraSettings),
+    /// Maps the log-average luminance of the image to `key` middle grey (Reinhard et al. 2002),
+    /// then shifts by `compensation` stops.
+    Auto { key: f32, compensation: f32 }
+}
+
+/// Luminance floor keeping black pixels from pulling the log-average to zero.
+const DELTA: f32 = 1e-4;
+
+/// Geometric mean of the luminance of `image` in `working_space`, ignoring invalid pixels.
+pub fn log_average_luminance(image: &[Color], working_space: ColorSpace) -> f32 {
+    let mut sum = 0.;
+    let mut n = 0;
+    for &c in image {
+        let l = working_space.luminance(c);
+        if l.is_finite() && l >= 0. {
+            sum += (DELTA + l).ln() as f64;
+            n += 1;
+        }
+    }
+
+    if n == 0 {
+        return 0.;
+    }
+
+    (sum / n as f64).exp() as f32
+}
+
+#[allow(dead_code)]
+impl Exposure {
+    /// Auto exposure to 18% grey.
+    pub fn auto() -> Self {
+        Exposure::Auto { key: 0.18, compensation: 0. }
+    }
+
+    /// Multiplier for `image` in `working_space`, which auto exposure is metered on.
+    pub fn multiplier(&self, image: &[Color], working_space: ColorSpace) -> f32 {
+        match *self {
+            Exposure::Manual(m) => m,
+            Exposure::Physical(settings) => settings.multiplier(),
+            Exposure::Auto { key, compensation } => {
+                let average = log_average_luminance(image, working_space);
+                if average <= 0. {
+                    return compensation.exp2();
+                }
+                key / average * compensation.exp2()
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// One image channel, `width * height` values in scanline order starting at the top row.
+pub struct Channel {
+    pub name: String,
+    pub values: Vec<f32>
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn box2i(width: i32, height: i32) -> Vec<u8> {
+    [0, 0, width - 1, height - 1].iter().flat_map(|v: &i32| v.to_le_bytes()).collect()
+}
+
+/// Writes an uncompressed scanline OpenEXR file with 32-bit float channels.
+pub fn write(file_name: &str, width: i32, height: i32, channels: &[Channel]) -> io::Result<()> {
+    // the format requires channels sorted by name, in the header and in every scanline
+    let mut channels: Vec<&Channel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut chlist = Vec::new();
+    for c in &channels {
+        chlist.extend_from_slice(c.name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear, reserved
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // x sampling
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // y sampling
+    }
+    chlist.push(0);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]);
+    header.extend_from_slice(&2u32.to_le_bytes());
+
+    attribute(&mut header, "channels", "chlist", &chlist);
+    attribute(&mut header, "compression", "compression", &[0]);
+    attribute(&mut header, "dataWindow", "box2i", &box2i(width, height));
+    attribute(&mut header, "displayWindow", "box2i", &box2i(width, height));
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+
+    let line_size = width as usize * channels.len() * 4;
+    let first_line = header.len() + height as usize * 8;
+
+    let mut o = BufWriter::new(File::create(file_name)?);
+    o.write_all(&header)?;
+
+    // offset table, one block per scanline of y, size and data
+    for y in 0..height as usize {
+        o.write_all(&((first_line + y * (8 + line_size)) as u64).to_le_bytes())?;
+    }
+
+    for y in 0..height as usize {
+        o.write_all(&(y as i32).to_le_bytes())?;
+        o.write_all(&(line_size as i32).to_le_bytes())?;
+
+        for c in &channels {
+            for v in &c.values[y * width as usize..(y + 1) * width as usize] {
+                o.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+
+    o.flush()
+}
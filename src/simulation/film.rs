@@ -0,0 +1,341 @@
+use std::io::{self, Read, Write};
+
+use crate::math::vector::{Color, Point2D, Vector2};
+use crate::simulation::adaptive::PixelStats;
+use crate::simulation::aov::{Aov, AovMode, AovSample};
+use crate::simulation::filter::Filter;
+
+/// Floating point image accumulating filtered samples, in the working color space.
+/// A film may cover only a region of the image starting at (`x0`, `y0`), pixel coordinates are always image wide.
+/// AOVs are not filtered, each sample only counts for the pixel it was taken in.
+/// Splats are light arriving at arbitrary pixels independent of the pixel being sampled, as light
+/// paths traced to the camera bring. They are summed unfiltered and scaled when developing.
+pub struct Film {
+    x0: i32,
+    y0: i32,
+    width: i32,
+    height: i32,
+    filter: Filter,
+    sum: Vec<Color>,
+    weight: Vec<f32>,
+    stats: Vec<PixelStats>,
+    aovs: Vec<Aov>,
+    aov_sum: Vec<Color>,
+    aov_count: Vec<u32>,
+    splat: Vec<Color>
+}
+
+#[allow(dead_code)]
+impl Film {
+    pub fn new(width: i32, height: i32, filter: Filter) -> Self {
+        Self::region(0, 0, width, height, filter)
+    }
+
+    pub fn region(x0: i32, y0: i32, width: i32, height: i32, filter: Filter) -> Self {
+        let count = (width * height) as usize;
+
+        Self {
+            x0,
+            y0,
+            width,
+            height,
+            filter,
+            sum: vec![Color::zero(); count],
+            weight: vec![0.; count],
+            stats: vec![PixelStats::default(); count],
+            aovs: Vec::new(),
+            aov_sum: Vec::new(),
+            aov_count: Vec::new(),
+            splat: vec![Color::zero(); count]
+        }
+    }
+
+    /// Also accumulates the given AOVs.
+    pub fn with_aovs(mut self, aovs: &[Aov]) -> Self {
+        let count = (self.width * self.height) as usize * aovs.len();
+
+        self.aovs = aovs.to_vec();
+        self.aov_sum = vec![Color::zero(); count];
+        self.aov_count = vec![0; count];
+        self
+    }
+
+    pub fn aovs(&self) -> &[Aov] {
+        &self.aovs
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        (x - self.x0 + (y - self.y0) * self.width) as usize
+    }
+
+    /// Empty film for rendering pixels [x0, x1) x [y0, y1) on their own. It extends past the
+    /// tile by the filter radius and starts with the sample statistics of the tile pixels.
+    pub fn tile(&self, x0: i32, y0: i32, x1: i32, y1: i32) -> Film {
+        let margin = self.filter.radius().ceil() as i32;
+
+        let rx0 = (x0 - margin).max(self.x0);
+        let ry0 = (y0 - margin).max(self.y0);
+        let rx1 = (x1 + margin).min(self.x0 + self.width);
+        let ry1 = (y1 + margin).min(self.y0 + self.height);
+
+        let n = self.aovs.len();
+        let mut tile = Film::region(rx0, ry0, rx1 - rx0, ry1 - ry0, self.filter).with_aovs(&self.aovs);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let src = self.index(x, y);
+                let dst = tile.index(x, y);
+
+                tile.stats[dst] = self.stats[src];
+                tile.aov_sum[dst * n..(dst + 1) * n].copy_from_slice(&self.aov_sum[src * n..(src + 1) * n]);
+                tile.aov_count[dst * n..(dst + 1) * n].copy_from_slice(&self.aov_count[src * n..(src + 1) * n]);
+            }
+        }
+
+        tile
+    }
+
+    /// Adds the samples of a film returned by `tile`. Statistics and AOVs are taken over for the
+    /// pixels the tile sampled, which no other tile touches.
+    pub fn merge(&mut self, tile: &Film) {
+        let n = self.aovs.len();
+
+        for y in tile.y0..tile.y0 + tile.height {
+            for x in tile.x0..tile.x0 + tile.width {
+                let src = tile.index(x, y);
+                let dst = self.index(x, y);
+
+                self.sum[dst] += tile.sum[src];
+                self.weight[dst] += tile.weight[src];
+                if tile.stats[src].count() > 0 {
+                    self.stats[dst] = tile.stats[src];
+                    self.aov_sum[dst * n..(dst + 1) * n].copy_from_slice(&tile.aov_sum[src * n..(src + 1) * n]);
+                    self.aov_count[dst * n..(dst + 1) * n].copy_from_slice(&tile.aov_count[src * n..(src + 1) * n]);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sum.fill(Color::zero());
+        self.weight.fill(0.);
+        self.stats.fill(PixelStats::default());
+        self.aov_sum.fill(Color::zero());
+        self.aov_count.fill(0);
+        self.splat.fill(Color::zero());
+    }
+
+    /// Adds a sample at continuous raster position `p` (pixel (x, y) covers [x, x + 1) x [y, y + 1))
+    /// to every pixel whose filter support contains it.
+    pub fn add_sample(&mut self, p: Point2D, color: Color) {
+        let r = self.filter.radius();
+
+        let x0 = ((p.x - 0.5 - r).ceil() as i32).max(self.x0);
+        let x1 = ((p.x - 0.5 + r).floor() as i32).min(self.x0 + self.width - 1);
+        let y0 = ((p.y - 0.5 - r).ceil() as i32).max(self.y0);
+        let y1 = ((p.y - 0.5 + r).floor() as i32).min(self.y0 + self.height - 1);
+
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let d = Vector2::new(x as f32 + 0.5 - p.x, y as f32 + 0.5 - p.y);
+                let w = self.filter.evaluate(d);
+                if w == 0. {
+                    continue;
+                }
+
+                let i = self.index(x, y);
+                self.sum[i] += color * w;
+                self.weight[i] += w;
+            }
+        }
+    }
+
+    /// Adds light at continuous raster position `p` to the pixel containing it, dropped outside the film.
+    pub fn add_splat(&mut self, p: Point2D, color: Color) {
+        let (x, y) = (p.x.floor() as i32, p.y.floor() as i32);
+        if x < self.x0 || y < self.y0 || x >= self.x0 + self.width || y >= self.y0 + self.height {
+            return;
+        }
+
+        let i = self.index(x, y);
+        self.splat[i] += color;
+    }
+
+    /// Sum of the light splatted to pixel (x, y), to be divided by the samples per pixel of the image.
+    pub fn splat(&self, x: i32, y: i32) -> Color {
+        self.splat[self.index(x, y)]
+    }
+
+    /// Records the luminance of a sample generated for pixel (x, y), independent of where it was splatted.
+    pub fn record(&mut self, x: i32, y: i32, luminance: f32) {
+        let i = self.index(x, y);
+        self.stats[i].add(luminance);
+    }
+
+    pub fn stats(&self, x: i32, y: i32) -> PixelStats {
+        self.stats[self.index(x, y)]
+    }
+
+    /// Adds the AOVs written by a sample taken in pixel (x, y).
+    pub fn add_aovs(&mut self, x: i32, y: i32, sample: &AovSample) {
+        let base = self.index(x, y) * self.aovs.len();
+
+        for (k, &aov) in self.aovs.iter().enumerate() {
+            // light not reaching the camera is a zero sample, missing geometry data is no sample
+            let value = match sample.get(aov) {
+                Some(value) => value,
+                None if aov.is_radiance() => Color::zero(),
+                None => continue
+            };
+
+            let i = base + k;
+            match aov.mode() {
+                AovMode::Average => self.aov_sum[i] += value,
+                AovMode::First if self.aov_count[i] == 0 => self.aov_sum[i] = value,
+                AovMode::First => continue
+            }
+            self.aov_count[i] += 1;
+        }
+    }
+
+    /// Value of an AOV in pixel (x, y), zero where it was never written.
+    pub fn aov(&self, x: i32, y: i32, aov: Aov) -> Color {
+        let i = self.index(x, y);
+
+        if aov == Aov::SampleCount {
+            let n = self.stats[i].count() as f32;
+            return Color::new(n, n, n);
+        }
+
+        let Some(k) = self.aovs.iter().position(|&a| a == aov) else {
+            return Color::zero();
+        };
+
+        let i = i * self.aovs.len() + k;
+        match (aov.mode(), self.aov_count[i]) {
+            (_, 0) => Color::zero(),
+            (AovMode::First, _) => self.aov_sum[i],
+            (AovMode::Average, n) => self.aov_sum[i] / n as f32
+        }
+    }
+
+    /// Writes the raw accumulation buffers, little endian.
+    pub fn save(&self, o: &mut impl Write) -> io::Result<()> {
+        for i in 0..self.sum.len() {
+            let (count, mean, m2) = self.stats[i].raw();
+
+            for v in [self.sum[i].x, self.sum[i].y, self.sum[i].z, self.weight[i]] {
+                o.write_all(&v.to_le_bytes())?;
+            }
+            o.write_all(&count.to_le_bytes())?;
+            o.write_all(&mean.to_le_bytes())?;
+            o.write_all(&m2.to_le_bytes())?;
+        }
+
+        for (sum, count) in self.aov_sum.iter().zip(&self.aov_count) {
+            for v in [sum.x, sum.y, sum.z] {
+                o.write_all(&v.to_le_bytes())?;
+            }
+            o.write_all(&count.to_le_bytes())?;
+        }
+
+        for splat in &self.splat {
+            for v in [splat.x, splat.y, splat.z] {
+                o.write_all(&v.to_le_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads buffers written by `save` for a film of the same size.
+    pub fn load(&mut self, i: &mut impl Read) -> io::Result<()> {
+        let mut b = [0u8; 4];
+        let mut next = |i: &mut dyn Read| -> io::Result<[u8; 4]> {
+            i.read_exact(&mut b)?;
+            Ok(b)
+        };
+
+        for p in 0..self.sum.len() {
+            let x = f32::from_le_bytes(next(i)?);
+            let y = f32::from_le_bytes(next(i)?);
+            let z = f32::from_le_bytes(next(i)?);
+            self.sum[p] = Color::new(x, y, z);
+            self.weight[p] = f32::from_le_bytes(next(i)?);
+
+            let count = u32::from_le_bytes(next(i)?);
+            let mean = f32::from_le_bytes(next(i)?);
+            let m2 = f32::from_le_bytes(next(i)?);
+            self.stats[p] = PixelStats::from_raw(count, mean, m2);
+        }
+
+        for p in 0..self.aov_sum.len() {
+            let x = f32::from_le_bytes(next(i)?);
+            let y = f32::from_le_bytes(next(i)?);
+            let z = f32::from_le_bytes(next(i)?);
+            self.aov_sum[p] = Color::new(x, y, z);
+            self.aov_count[p] = u32::from_le_bytes(next(i)?);
+        }
+
+        for p in 0..self.splat.len() {
+            let x = f32::from_le_bytes(next(i)?);
+            let y = f32::from_le_bytes(next(i)?);
+            let z = f32::from_le_bytes(next(i)?);
+            self.splat[p] = Color::new(x, y, z);
+        }
+
+        Ok(())
+    }
+
+    /// Filtered pixel value, black where no sample landed.
+    pub fn pixel(&self, x: i32, y: i32) -> Color {
+        let i = self.index(x, y);
+        let w = self.weight[i];
+
+        if w <= 0. {
+            return Color::zero();
+        }
+
+        self.sum[i] / w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_are_weighted_averages_of_their_samples() {
+        let mut film = Film::new(3, 3, Filter::tent(1.));
+
+        // same color everywhere, the normalized pixels keep it whatever the weights
+        for j in 0..8 {
+            for i in 0..8 {
+                film.add_sample(Point2D::new((i as f32 + 0.5) * 3. / 8., (j as f32 + 0.5) * 3. / 8.), Color::new(0.25, 0.5, 1.));
+            }
+        }
+        for y in 0..3 {
+            for x in 0..3 {
+                let c = film.pixel(x, y);
+                assert!((c.x - 0.25).abs() < 1e-5 && (c.y - 0.5).abs() < 1e-5 && (c.z - 1.).abs() < 1e-5);
+            }
+        }
+
+        // a sample only reaches the pixels inside the filter support
+        film.clear();
+        film.add_sample(Point2D::new(0.5, 0.5), Color::new(1., 1., 1.));
+        assert_eq!(film.pixel(0, 0).x, 1.);
+        assert_eq!([film.pixel(1, 0).x, film.pixel(0, 1).x, film.pixel(2, 2).x], [0., 0., 0.]);
+    }
+}
@@ -0,0 +1,173 @@
+use std::f32::consts::PI;
+
+use std::hash::Hasher;
+
+use crate::math::vector::Vector2;
+use crate::simulation::checkpoint::SceneHasher;
+
+/// Pixel reconstruction filter, separable in x and y. Radius is in pixels.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug)]
+pub enum Filter {
+    Box { radius: f32 },
+    Tent { radius: f32 },
+    Gaussian { radius: f32, sigma: f32 },
+    /// Mitchell-Netravali cubic, B = C = 1/3 is the recommended balance of blur and ringing.
+    Mitchell { radius: f32, b: f32, c: f32 },
+    /// Lanczos windowed sinc, `tau` is the number of lobes of the window.
+    Lanczos { radius: f32, tau: f32 }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+fn mitchell_1d(x: f32, b: f32, c: f32) -> f32 {
+    let x = x.abs();
+
+    if x > 2. {
+        0.
+    } else if x > 1. {
+        ((-b - 6. * c) * x * x * x + (6. * b + 30. * c) * x * x + (-12. * b - 48. * c) * x + (8. * b + 24. * c)) / 6.
+    } else {
+        ((12. - 9. * b - 6. * c) * x * x * x + (-18. + 12. * b + 6. * c) * x * x + (6. - 2. * b)) / 6.
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    let x = x.abs();
+    if x < 1e-5 {
+        return 1.;
+    }
+
+    (PI * x).sin() / (PI * x)
+}
+
+#[allow(dead_code)]
+impl Filter {
+    pub fn tent(radius: f32) -> Self {
+        Filter::Tent { radius }
+    }
+
+    pub fn gaussian(radius: f32) -> Self {
+        Filter::Gaussian { radius, sigma: radius / 3. }
+    }
+
+    pub fn mitchell(radius: f32) -> Self {
+        Filter::Mitchell { radius, b: 1. / 3., c: 1. / 3. }
+    }
+
+    pub fn lanczos(radius: f32) -> Self {
+        Filter::Lanczos { radius, tau: 3. }
+    }
+
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius, .. } => radius
+        }
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let r = self.radius();
+        if x.abs() > r {
+            return 0.;
+        }
+
+        match *self {
+            Filter::Box { .. } => 1.,
+            Filter::Tent { radius } => radius - x.abs(),
+            Filter::Gaussian { radius, sigma } => {
+                let g = |v: f32| (-v * v / (2. * sigma * sigma)).exp();
+                (g(x) - g(radius)).max(0.)
+            }
+            Filter::Mitchell { radius, b, c } => mitchell_1d(2. * x / radius, b, c),
+            Filter::Lanczos { radius, tau } => {
+                let x = x / radius * tau;
+                sinc(x) * sinc(x / tau)
+            }
+        }
+    }
+
+    pub fn fingerprint(&self, h: &mut SceneHasher) {
+        match *self {
+            Filter::Box { radius } => {
+                h.write_u8(0);
+                h.write_f32(radius);
+            }
+            Filter::Tent { radius } => {
+                h.write_u8(1);
+                h.write_f32(radius);
+            }
+            Filter::Gaussian { radius, sigma } => {
+                h.write_u8(2);
+                h.write_f32(radius);
+                h.write_f32(sigma);
+            }
+            Filter::Mitchell { radius, b, c } => {
+                h.write_u8(3);
+                h.write_f32(radius);
+                h.write_f32(b);
+                h.write_f32(c);
+            }
+            Filter::Lanczos { radius, tau } => {
+                h.write_u8(4);
+                h.write_f32(radius);
+                h.write_f32(tau);
+            }
+        }
+    }
+
+    /// Weight of a sample at offset `d` from the pixel center, may be negative.
+    pub fn evaluate(&self, d: Vector2) -> f32 {
+        self.evaluate_1d(d.x) * self.evaluate_1d(d.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Integral of the weights over the filter support, by the midpoint rule.
+    fn integral(filter: &Filter) -> f32 {
+        let r = filter.radius();
+        let n = 400;
+        let step = 2. * r / n as f32;
+
+        let mut sum = 0.;
+        for j in 0..n {
+            for i in 0..n {
+                let d = Vector2::new(-r + (i as f32 + 0.5) * step, -r + (j as f32 + 0.5) * step);
+                sum += filter.evaluate(d) as f64;
+            }
+        }
+
+        (sum * (step * step) as f64) as f32
+    }
+
+    #[test]
+    fn weights_integrate_to_one() {
+        // the radii the kernels are defined for, 1 pixel for the box and tent, 2 for Mitchell
+        for filter in [Filter::Box { radius: 0.5 }, Filter::tent(1.), Filter::mitchell(2.), Filter::Mitchell { radius: 2., b: 1., c: 0. }] {
+            assert!((integral(&filter) - 1.).abs() < 1e-3, "{:?} integrates to {}", filter, integral(&filter));
+        }
+    }
+
+    #[test]
+    fn weights_peak_at_center_and_vanish_outside() {
+        for filter in [Filter::default(), Filter::tent(1.5), Filter::gaussian(1.5), Filter::mitchell(2.), Filter::lanczos(3.)] {
+            let r = filter.radius();
+            let center = filter.evaluate(Vector2::new(0., 0.));
+
+            assert!(center > 0. && integral(&filter) > 0., "{:?}", filter);
+            assert!(filter.evaluate(Vector2::new(0.3 * r, 0.2 * r)) <= center, "{:?}", filter);
+            assert_eq!(filter.evaluate(Vector2::new(r * 1.01, 0.)), 0., "{:?}", filter);
+            assert_eq!(filter.evaluate(Vector2::new(0., -r * 1.01)), 0., "{:?}", filter);
+        }
+    }
+}
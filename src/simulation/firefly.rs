@@ -0,0 +1,137 @@
+use crate::math::color::ColorSpace;
+use crate::math::vector::Color;
+use crate::simulation::checkpoint::SceneHasher;
+
+/// Per sample radiance limits against fireflies from rare, bright paths such as caustics. Light
+/// arriving after one bounce is limited to `direct`, after more bounces to `indirect`. Emitters and
+/// the background seen directly are left alone. Clamping darkens the image, the lost energy is the
+/// price of the lower variance.
+#[derive(Copy, Clone, Debug)]
+pub struct SampleClamp {
+    pub direct: f32,
+    pub indirect: f32
+}
+
+#[allow(dead_code)]
+impl SampleClamp {
+    pub fn new(direct: f32, indirect: f32) -> Self {
+        Self { direct, indirect }
+    }
+
+    pub fn threshold(&self, bounce: i32) -> f32 {
+        match bounce {
+            0 => f32::INFINITY,
+            1 => self.direct,
+            _ => self.indirect
+        }
+    }
+
+    /// `light` scaled down so its largest component is at most the threshold for `bounce`, keeping
+    /// its hue. None when it is within the limit.
+    pub fn apply(&self, bounce: i32, light: Color) -> Option<Color> {
+        let threshold = self.threshold(bounce);
+        let peak = light.x.max(light.y).max(light.z);
+
+        if peak <= threshold {
+            return None;
+        }
+
+        Some(light * (threshold / peak))
+    }
+
+    pub fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_f32(self.direct);
+        h.write_f32(self.indirect);
+    }
+}
+
+/// Post-filter replacing pixels far brighter than their neighbours. A pixel is an outlier when
+/// its luminance exceeds the mean of its 8 neighbours by more than `threshold` standard deviations
+/// of them, it is then scaled down to that bound. The deviation is at least `min_deviation` of the
+/// mean, so smooth regions do not flag slightly brighter pixels.
+#[derive(Copy, Clone, Debug)]
+pub struct OutlierRejection {
+    pub threshold: f32,
+    pub min_deviation: f32
+}
+
+impl Default for OutlierRejection {
+    fn default() -> Self {
+        Self {
+            threshold: 4.,
+            min_deviation: 0.1
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl OutlierRejection {
+    pub fn new(threshold: f32) -> Self {
+        Self { threshold, ..Self::default() }
+    }
+
+    pub fn with_min_deviation(mut self, min_deviation: f32) -> Self {
+        self.min_deviation = min_deviation;
+        self
+    }
+
+    /// Filters a `width` x `height` linear image in `working_space` in place, returns the number of
+    /// pixels changed.
+    pub fn apply(&self, image: &mut [Color], width: i32, height: i32, working_space: ColorSpace) -> usize {
+        let luminance: Vec<f32> = image.iter().map(|&c| working_space.luminance(c)).collect();
+
+        let mut rejected = 0;
+        for y in 0..height {
+            for x in 0..width {
+                let p = (x + y * width) as usize;
+
+                let mut sum = 0.;
+                let mut sum_sq = 0.;
+                let mut n = 0;
+                for qy in (y - 1).max(0)..(y + 2).min(height) {
+                    for qx in (x - 1).max(0)..(x + 2).min(width) {
+                        if qx == x && qy == y {
+                            continue;
+                        }
+
+                        let l = luminance[(qx + qy * width) as usize];
+                        sum += l;
+                        sum_sq += l * l;
+                        n += 1;
+                    }
+                }
+                if n == 0 {
+                    continue;
+                }
+
+                let mean = sum / n as f32;
+                let deviation = (sum_sq / n as f32 - mean * mean).max(0.).sqrt().max(self.min_deviation * mean.abs());
+                let bound = mean + self.threshold * deviation;
+
+                if luminance[p] > bound && luminance[p] > 0. {
+                    image[p] *= bound.max(0.) / luminance[p];
+                    rejected += 1;
+                }
+            }
+        }
+
+        rejected
+    }
+}
+
+/// What firefly suppression changed during the last render.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct FireflyStats {
+    pub samples: u64,
+    /// Samples with at least one contribution clamped.
+    pub clamped_samples: u64,
+    /// Pixels changed by outlier rejection when the image was last developed.
+    pub rejected_pixels: usize
+}
+
+#[allow(dead_code)]
+impl FireflyStats {
+    pub fn clamped_fraction(&self) -> f64 {
+        self.clamped_samples as f64 / self.samples.max(1) as f64
+    }
+}
@@ -1,45 +1,77 @@
-use crate::math::vector::Point3D;
+use std::f32::consts::PI;
+use std::hash::Hasher;
+use std::sync::Arc;
+
+use crate::math::sampling;
+use crate::math::vector::{Onb, Point2D, Point3D};
 use crate::math::vector::Vector3;
+use crate::simulation::checkpoint::SceneHasher;
+use crate::simulation::material::Material;
 use crate::simulation::ray::Ray;
+use crate::simulation::spectrum::Wavelength;
 
 #[allow(dead_code)]
+#[derive(Default)]
 pub struct HitRecord {
     p: Point3D,
     normal: Vector3,
+    geometric_normal: Vector3,
+    uv: Point2D,
+    dpdu: Vector3,
+    dpdv: Vector3,
     t: f32,
-    front_face: bool
+    front_face: bool,
+    wavelength: Option<Wavelength>,
+    material: Option<Arc<dyn Material>>,
+    material_id: u32,
+    object_id: u32
 }
 
 #[allow(dead_code)]
 pub struct Sphere {
     center: Point3D,
-    radius: f32
+    radius: f32,
+    material: Option<Arc<dyn Material>>
 }
 
 #[allow(dead_code)]
+#[derive(Default)]
 pub struct HittableList {
-    objects: Vec<Box<dyn Hittable>>
+    objects: Vec<Box<dyn Hittable>>,
+    /// Distinct materials of the objects, identified by address.
+    materials: Vec<usize>,
+    /// Per object id into `materials` plus one, 0 for objects without a single material.
+    material_ids: Vec<u32>
 }
 
 #[allow(dead_code)]
-pub trait Hittable {
+pub trait Hittable: Send + Sync {
     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
-}
 
-impl Default for HittableList {
-    fn default() -> Self {
-        Self { objects: Vec::new()}
+    /// Surface area, 0 for objects that can not be sampled.
+    fn area(&self) -> f32 {
+        0.
     }
-}
 
-impl Default for HitRecord {
-    fn default() -> Self {
-        Self {
-            p: Vector3::zero(),
-            normal: Vector3::zero(),
-            t: 0.,
-            front_face: false
-        }
+    /// Point uniformly distributed over the surface for a sample `u` in [0, 1)^2, recorded as if
+    /// hit from outside. None for objects that can not be sampled.
+    fn sample_surface(&self, _u: Point2D, _wavelength: Option<Wavelength>) -> Option<HitRecord> {
+        None
+    }
+
+    /// Whether the surface emits light and may be sampled as an area light.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Material of the whole surface, None for objects without one or with several.
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        None
+    }
+
+    /// Hashes what affects the rendered image, see `SceneHasher`.
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str(std::any::type_name::<Self>());
     }
 }
 
@@ -47,31 +79,166 @@ impl Default for HitRecord {
 impl HitRecord {
     pub fn new(p: Point3D, t: f32, ray: &Ray, outward_normal: Vector3) -> Self {
         let front_face = ray.direction().dot(outward_normal) < 0.;
+        let normal = if front_face {outward_normal} else {-outward_normal};
+        let frame = Onb::from_w(normal);
 
         Self { 
-            p: p, 
-            normal:  if front_face {outward_normal} else {-outward_normal},
+            p, 
+            normal,
+            geometric_normal: normal,
+            uv: Point2D::zero(),
+            dpdu: frame.u,
+            dpdv: frame.v,
             t, 
-            front_face 
+            front_face,
+            wavelength: ray.wavelength(),
+            material: None,
+            material_id: 0,
+            object_id: 0
         }
     }
 
+    /// Attaches the surface parameterization at the hit point.
+    /// `dpdu` / `dpdv` are the partial derivatives of the position along the uv coordinates.
+    pub fn with_uv(mut self, uv: Point2D, dpdu: Vector3, dpdv: Vector3) -> Self {
+        self.uv = uv;
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+
+        self
+    }
+
+    pub fn with_material(mut self, material: Option<Arc<dyn Material>>) -> Self {
+        self.material = material;
+        self
+    }
+
+    /// Id of the material in the world list, 0 without material.
+    pub fn set_material_id(&mut self, material_id: u32) {
+        self.material_id = material_id;
+    }
+
+    /// Index of the hit object in the world list, plus one.
+    pub fn set_object_id(&mut self, object_id: u32) {
+        self.object_id = object_id;
+    }
+
+    pub fn material_id(&self) -> u32 {
+        self.material_id
+    }
+
+    pub fn object_id(&self) -> u32 {
+        self.object_id
+    }
+
     pub fn p(&self) -> Point3D {
         self.p
     }
 
+    /// Shading normal, faces against the incoming ray.
     pub fn normal(&self) -> Vector3 {
         self.normal
     }
 
+    /// True surface normal, faces against the incoming ray.
+    pub fn geometric_normal(&self) -> Vector3 {
+        self.geometric_normal
+    }
+
+    /// Replaces the shading normal. Normals dipping below the geometric surface
+    /// (e.g. from strong normal maps) are pulled back just above it.
+    pub fn set_shading_normal(&mut self, n: Vector3) {
+        const MIN_COS: f32 = 0.01;
+
+        let ng = self.geometric_normal;
+        let cos = n.dot(ng);
+
+        let n = if cos < MIN_COS {
+            n + ng * (MIN_COS - cos)
+        } else {
+            n
+        };
+
+        if n.magnitude_squared() < 1e-12 {
+            self.normal = ng;
+            return;
+        }
+
+        self.normal = n.normalized();
+    }
+
+    pub fn uv(&self) -> Point2D {
+        self.uv
+    }
+
+    pub fn dpdu(&self) -> Vector3 {
+        self.dpdu
+    }
+
+    pub fn dpdv(&self) -> Vector3 {
+        self.dpdv
+    }
+
     pub fn t(&self) -> f32 {
         self.t
     }
+
+    pub fn front_face(&self) -> bool {
+        self.front_face
+    }
+
+    /// Wavelength of the incoming path, None when rendering in RGB.
+    pub fn wavelength(&self) -> Option<Wavelength> {
+        self.wavelength
+    }
+
+    pub fn material(&self) -> Option<&Arc<dyn Material>> {
+        self.material.as_ref()
+    }
+
+    /// Tangent frame around the shading normal, `u` follows `dpdu`.
+    pub fn shading_frame(&self) -> Onb {
+        Onb::from_wu(self.normal, self.dpdu)
+    }
 }
 
+#[allow(dead_code)]
 impl Sphere {
     pub fn new(center: Point3D, radius: f32) -> Self {
-        Self { center, radius }
+        Self { center, radius, material: None }
+    }
+
+    pub fn with_material<M: Material + 'static>(self, material: M) -> Self {
+        self.with_shared_material(Arc::new(material))
+    }
+
+    pub fn with_shared_material(mut self, material: Arc<dyn Material>) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    fn record(&self, ray: &Ray, t: f32) -> HitRecord {
+        let p = ray.at(t);
+        let outward_normal = (p - self.center) / self.radius;
+
+        // u: angle around the Y axis from X=-1, v: angle from Y=-1 to Y=+1
+        let theta = (-outward_normal.y).clamp(-1., 1.).acos();
+        let phi = (-outward_normal.z).atan2(outward_normal.x) + PI;
+
+        let d = p - self.center;
+        let rho = (d.x * d.x + d.z * d.z).sqrt();
+
+        let dpdu = Vector3::new(d.z, 0., -d.x) * (2. * PI);
+        let dpdv = if rho > 1e-6 {
+            Vector3::new(-d.x * d.y / rho, rho, -d.z * d.y / rho) * PI
+        } else {
+            // at the poles the parameterization collapses, any tangent orthogonal to the normal will do
+            Onb::from_w(outward_normal).v * (self.radius * PI)
+        };
+
+        HitRecord::new(p, t, ray, outward_normal)
+            .with_uv(Point2D::new(phi / (2. * PI), theta / PI), dpdu, dpdv)
+            .with_material(self.material.clone())
     }
 }
 
@@ -92,35 +259,84 @@ impl Hittable for Sphere {
             let in_range = t_min < t && t < t_max;
 
             if in_range {
-                let p = ray.at(t);
-                let outward_normal = (p - self.center) / self.radius;
-
-                return Some(HitRecord::new(p, t, ray, outward_normal));
+                return Some(self.record(ray, t));
             }
 
             let t = (-half_b + root) / a;
             let in_range = t_min < t && t < t_max;
 
             if in_range {
-                let p = ray.at(t);
-                let outward_normal = (p - self.center) / self.radius;
-
-                return Some(HitRecord::new(p, t, ray, outward_normal));
+                return Some(self.record(ray, t));
             }
         } 
 
         None
     }
+
+    fn area(&self) -> f32 {
+        4. * PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self, u: Point2D, wavelength: Option<Wavelength>) -> Option<HitRecord> {
+        let n = sampling::uniform_sphere(u);
+
+        // a ray from just outside along the inward normal reaches the point at t = 1
+        let ray = Ray::new(&(self.center + n * (self.radius + 1.)), &-n).with_wavelength(wavelength);
+        Some(self.record(&ray, 1.))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.as_ref().is_some_and(|m| m.is_emissive())
+    }
+
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        self.material.as_ref()
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("Sphere");
+        h.write_vector(self.center);
+        h.write_f32(self.radius);
+        match &self.material {
+            Some(m) => m.fingerprint(h),
+            None => h.write_u8(0)
+        }
+    }
 }
 
 #[allow(dead_code)]
 impl HittableList {
     pub fn clear(&mut self) {
-        self.objects.clear()
+        self.objects.clear();
+        self.materials.clear();
+        self.material_ids.clear();
     }
 
+    /// Objects sharing one material instance get the same material id, ids start at 1 in the order
+    /// the materials are first added.
     pub fn add<T: Hittable + 'static>(&mut self, object: T) {
+        let id = match object.material() {
+            Some(material) => {
+                let key = Arc::as_ptr(material) as *const () as usize;
+                let index = match self.materials.iter().position(|&k| k == key) {
+                    Some(index) => index,
+                    None => {
+                        self.materials.push(key);
+                        self.materials.len() - 1
+                    }
+                };
+                index as u32 + 1
+            }
+            None => 0
+        };
+
         self.objects.push(Box::new(object));
+        self.material_ids.push(id);
+    }
+
+    /// Objects in the order they were added, `HitRecord::object_id` minus one indexes this.
+    pub fn objects(&self) -> &[Box<dyn Hittable>] {
+        &self.objects
     }
 }
 
@@ -130,8 +346,12 @@ impl Hittable for HittableList {
         let mut hit_anything: bool = false;
         let mut closest: f32 = t_max;
         
-        for obj in &self.objects {
-            if let Some(h) = obj.hit(ray, t_min, closest) {
+        for (i, obj) in self.objects.iter().enumerate() {
+            if let Some(mut h) = obj.hit(ray, t_min, closest) {
+                h.set_object_id(i as u32 + 1);
+                if self.material_ids[i] != 0 {
+                    h.set_material_id(self.material_ids[i]);
+                }
                 hit = h;
                 hit_anything = true;
                 closest = hit.t;
@@ -144,4 +364,12 @@ impl Hittable for HittableList {
             None
         }
     }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("HittableList");
+        h.write_usize(self.objects.len());
+        for obj in &self.objects {
+            obj.fingerprint(h);
+        }
+    }
 }
\ No newline at end of file
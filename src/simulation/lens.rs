@@ -0,0 +1,391 @@
+use std::fs;
+use std::io;
+
+use crate::math::vector::{Point2D, Point3D, Vector3};
+use crate::simulation::camera::{CameraModel, View};
+use crate::simulation::checkpoint::SceneHasher;
+use crate::simulation::microfacet;
+use crate::simulation::ray::Ray;
+
+/// Lens prescriptions are given in millimeters, scenes are in meters.
+const MM: f32 = 0.001;
+
+/// Exit pupil bounds are precomputed for this many rings of the film.
+const PUPIL_RINGS: usize = 64;
+/// Candidate points per axis on the rear element when searching the exit pupil of one ring.
+const PUPIL_GRID: usize = 128;
+
+/// One spherical surface of the lens, or the aperture stop when `radius` is 0.
+/// `thickness` is the distance to the next surface towards the film, `ior` that of the medium up to it.
+#[derive(Copy, Clone, Debug)]
+struct Interface {
+    radius: f32,
+    thickness: f32,
+    ior: f32,
+    aperture_radius: f32
+}
+
+/// Lens prescription: surfaces from the object side to the film, in scene units.
+#[derive(Clone, Debug)]
+pub struct Lens {
+    interfaces: Vec<Interface>
+}
+
+fn invalid(file_name: &str, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file_name, msg))
+}
+
+#[allow(dead_code)]
+impl Lens {
+    /// Reads a prescription, one surface per line as `radius thickness ior aperture` in millimeters
+    /// with the aperture given as a diameter. A radius of 0 marks the aperture stop, an IOR of 0 is
+    /// air. `#` starts a comment. The last thickness is replaced when the lens is focused.
+    pub fn load(file_name: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(file_name)?;
+
+        let mut interfaces = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let values: Vec<f32> = line.split_whitespace().map(|v| v.parse()).collect::<Result<_, _>>()
+                .map_err(|_| invalid(file_name, &format!("line {}: expected numbers", n + 1)))?;
+            let [radius, thickness, ior, aperture] = values[..] else {
+                return Err(invalid(file_name, &format!("line {}: expected radius, thickness, ior and aperture", n + 1)));
+            };
+
+            interfaces.push(Interface {
+                radius: radius * MM,
+                thickness: thickness * MM,
+                ior: if ior == 0. { 1. } else { ior },
+                aperture_radius: aperture * MM * 0.5
+            });
+        }
+
+        if interfaces.is_empty() {
+            return Err(invalid(file_name, "no lens surfaces"));
+        }
+
+        Ok(Self { interfaces })
+    }
+
+    /// Stops the aperture down to `diameter` millimeters, it can not be opened wider than the prescription allows.
+    pub fn with_aperture(mut self, diameter: f32) -> Self {
+        for stop in self.interfaces.iter_mut().filter(|i| i.radius == 0.) {
+            stop.aperture_radius = stop.aperture_radius.min(diameter * MM * 0.5);
+        }
+        self
+    }
+}
+
+/// Axis aligned rectangle on the rear lens plane.
+#[derive(Copy, Clone)]
+struct Bounds {
+    min: Point2D,
+    max: Point2D
+}
+
+impl Bounds {
+    fn empty() -> Self {
+        Self { min: Point2D::new(f32::INFINITY, f32::INFINITY), max: Point2D::new(f32::NEG_INFINITY, f32::NEG_INFINITY) }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min.x > self.max.x
+    }
+
+    fn add(&mut self, p: Point2D) {
+        self.min = Point2D::new(self.min.x.min(p.x), self.min.y.min(p.y));
+        self.max = Point2D::new(self.max.x.max(p.x), self.max.y.max(p.y));
+    }
+
+    fn expand(&mut self, d: f32) {
+        self.min = Point2D::new(self.min.x - d, self.min.y - d);
+        self.max = Point2D::new(self.max.x + d, self.max.y + d);
+    }
+
+    fn area(&self) -> f32 {
+        (self.max.x - self.min.x) * (self.max.y - self.min.y)
+    }
+
+    fn lerp(&self, t: Point2D) -> Point2D {
+        Point2D::new(self.min.x + (self.max.x - self.min.x) * t.x, self.min.y + (self.max.y - self.min.y) * t.y)
+    }
+}
+
+/// Ray/surface intersection of a spherical lens element centered on the axis at `z_center`,
+/// returns the distance and the normal facing the ray.
+fn intersect_spherical(radius: f32, z_center: f32, o: Point3D, d: Vector3) -> Option<(f32, Vector3)> {
+    let oc = o - Vector3::new(0., 0., z_center);
+
+    let a = d.dot(d);
+    let b = 2. * d.dot(oc);
+    let c = oc.dot(oc) - radius * radius;
+
+    let disc = b * b - 4. * a * c;
+    if disc < 0. {
+        return None;
+    }
+
+    let q = if b < 0. { -0.5 * (b - disc.sqrt()) } else { -0.5 * (b + disc.sqrt()) };
+    let (t0, t1) = (q / a, c / q);
+    let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+    // a convex surface seen from its front is hit first, a concave one second
+    let closer = (d.z > 0.) ^ (radius < 0.);
+    let t = if closer { t0 } else { t1 };
+    if t < 0. {
+        return None;
+    }
+
+    let n = (oc + d * t).normalized();
+    Some((t, if n.dot(d) > 0. { -n } else { n }))
+}
+
+/// Mirrors z between camera space, looking down +z from the film, and lens space where the
+/// surfaces lie along -z.
+fn flip(v: Vector3) -> Vector3 {
+    Vector3::new(v.x, v.y, -v.z)
+}
+
+/// Camera tracing rays from the film through a sequence of spherical lens elements (Kolb et al.
+/// 1995, after pbrt's realistic camera). Vignetting, distortion and the shape of out of focus
+/// highlights come from the lens itself. Rays are aimed at precomputed bounds of the exit pupil
+/// for the ring of the film they start from, so few of them are blocked inside the lens.
+pub struct RealisticCamera {
+    view: View,
+    interfaces: Vec<Interface>,
+    film_width: f32,
+    film_height: f32,
+    exit_pupils: Vec<Bounds>
+}
+
+#[allow(dead_code)]
+impl RealisticCamera {
+    /// Focuses `lens` at `focus_distance` meters in front of the film, for a film of
+    /// `film_diagonal` millimeters, e.g. 35 mm film is about 43.3.
+    pub fn new(lens: Lens, aspect_ratio: f32, film_diagonal: f32, focus_distance: f32) -> io::Result<Self> {
+        let diagonal = film_diagonal * MM;
+        let film_height = diagonal / (1. + aspect_ratio * aspect_ratio).sqrt();
+
+        let mut camera = Self {
+            view: View::default(),
+            interfaces: lens.interfaces,
+            film_width: film_height * aspect_ratio,
+            film_height,
+            exit_pupils: Vec::new()
+        };
+
+        let film_distance = camera.focus(focus_distance).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("lens can not focus at {} m", focus_distance))
+        })?;
+        camera.interfaces.last_mut().unwrap().thickness = film_distance;
+
+        camera.exit_pupils = (0..PUPIL_RINGS).map(|i| camera.exit_pupil(i)).collect();
+        Ok(camera)
+    }
+
+    pub fn with_view(mut self, view: View) -> Self {
+        self.view = view;
+        self
+    }
+
+    fn diagonal(&self) -> f32 {
+        (self.film_width * self.film_width + self.film_height * self.film_height).sqrt()
+    }
+
+    /// Distance from the film to the rear surface.
+    fn rear_z(&self) -> f32 {
+        self.interfaces.last().unwrap().thickness
+    }
+
+    /// Distance from the film to the front surface.
+    fn front_z(&self) -> f32 {
+        self.interfaces.iter().map(|i| i.thickness).sum()
+    }
+
+    /// Traces a camera space ray leaving the film out of the front of the lens, None when it is blocked.
+    fn trace_from_film(&self, o: Point3D, d: Vector3) -> Option<(Point3D, Vector3)> {
+        let (mut o, mut d) = (flip(o), flip(d));
+        let mut z = 0.;
+
+        for (i, interface) in self.interfaces.iter().enumerate().rev() {
+            z -= interface.thickness;
+
+            let (t, normal) = if interface.radius == 0. {
+                if d.z >= 0. {
+                    return None;
+                }
+                ((z - o.z) / d.z, None)
+            } else {
+                let (t, n) = intersect_spherical(interface.radius, z + interface.radius, o, d)?;
+                (t, Some(n))
+            };
+
+            let p = o + d * t;
+            if p.x * p.x + p.y * p.y > interface.aperture_radius * interface.aperture_radius {
+                return None;
+            }
+            o = p;
+
+            if let Some(n) = normal {
+                let eta_t = if i > 0 { self.interfaces[i - 1].ior } else { 1. };
+                let (wt, _) = microfacet::refract(-d.normalized(), n, eta_t / interface.ior)?;
+                d = wt;
+            }
+        }
+
+        Some((flip(o), flip(d)))
+    }
+
+    /// Traces a camera space ray entering the front of the lens to the film side, None when it is blocked.
+    fn trace_from_scene(&self, o: Point3D, d: Vector3) -> Option<(Point3D, Vector3)> {
+        let (mut o, mut d) = (flip(o), flip(d));
+        let mut z = -self.front_z();
+
+        for (i, interface) in self.interfaces.iter().enumerate() {
+            let (t, normal) = if interface.radius == 0. {
+                ((z - o.z) / d.z, None)
+            } else {
+                let (t, n) = intersect_spherical(interface.radius, z + interface.radius, o, d)?;
+                (t, Some(n))
+            };
+
+            let p = o + d * t;
+            if p.x * p.x + p.y * p.y > interface.aperture_radius * interface.aperture_radius {
+                return None;
+            }
+            o = p;
+
+            if let Some(n) = normal {
+                let eta_i = if i > 0 { self.interfaces[i - 1].ior } else { 1. };
+                let (wt, _) = microfacet::refract(-d.normalized(), n, interface.ior / eta_i)?;
+                d = wt;
+            }
+
+            z += interface.thickness;
+        }
+
+        Some((flip(o), flip(d)))
+    }
+
+    /// Principal plane and focal point z of a ray parallel to the axis at height `x_in`, leaving the lens as (o, d).
+    fn cardinal_points(x_in: f32, o: Point3D, d: Vector3) -> (f32, f32) {
+        let tf = -o.x / d.x;
+        let tp = (x_in - o.x) / d.x;
+
+        ((o + d * tp).z, (o + d * tf).z)
+    }
+
+    /// Film distance bringing `focus_distance` into focus, from the thick lens approximation.
+    fn focus(&self, focus_distance: f32) -> Option<f32> {
+        let x = 0.001 * self.diagonal();
+
+        let (o, d) = self.trace_from_scene(Point3D::new(x, 0., self.front_z() + 1.), Vector3::new(0., 0., -1.))?;
+        let (pz0, fz0) = Self::cardinal_points(x, o, d);
+
+        let (o, d) = self.trace_from_film(Point3D::new(x, 0., self.rear_z() - 1.), Vector3::new(0., 0., 1.))?;
+        let (pz1, _) = Self::cardinal_points(x, o, d);
+
+        // thin lens equation about the principal planes, solved for the film shift
+        let f = pz0 - fz0;
+        let z = focus_distance;
+        let c = (z - pz1 + pz0) * (z - pz1 + pz0 - 4. * f);
+        if c <= 0. {
+            return None;
+        }
+
+        let delta = 0.5 * (z - pz1 - pz0 - c.sqrt());
+        Some(self.rear_z() + delta).filter(|d| *d > 0.)
+    }
+
+    /// Bounds on the rear surface plane through which rays from film ring `ring` make it through the lens.
+    fn exit_pupil(&self, ring: usize) -> Bounds {
+        let half_diagonal = 0.5 * self.diagonal();
+        let r0 = ring as f32 / PUPIL_RINGS as f32 * half_diagonal;
+        let r1 = (ring + 1) as f32 / PUPIL_RINGS as f32 * half_diagonal;
+
+        let rear_radius = self.interfaces.last().unwrap().aperture_radius;
+        let extent = 1.5 * rear_radius;
+        let spacing = 2. * extent / PUPIL_GRID as f32;
+
+        let mut bounds = Bounds::empty();
+        for j in 0..PUPIL_GRID * PUPIL_GRID {
+            let rear = Point2D::new(
+                -extent + ((j % PUPIL_GRID) as f32 + 0.5) * spacing,
+                -extent + ((j / PUPIL_GRID) as f32 + 0.5) * spacing
+            );
+
+            let r = r0 + (r1 - r0) * (j as f32 + 0.5) / (PUPIL_GRID * PUPIL_GRID) as f32;
+            let film = Point3D::new(r, 0., 0.);
+            let target = Point3D::new(rear.x, rear.y, self.rear_z());
+
+            if self.trace_from_film(film, target - film).is_some() {
+                bounds.add(rear);
+            }
+        }
+
+        if bounds.is_empty() {
+            return Bounds { min: Point2D::new(-rear_radius, -rear_radius), max: Point2D::new(rear_radius, rear_radius) };
+        }
+
+        bounds.expand(spacing);
+        bounds
+    }
+
+    /// Point on the rear surface plane to aim at from the film point, and the area it was sampled from.
+    fn sample_exit_pupil(&self, film: Point3D, u: Point2D) -> (Point3D, f32) {
+        let r = (film.x * film.x + film.y * film.y).sqrt();
+        let ring = ((r / (0.5 * self.diagonal()) * PUPIL_RINGS as f32) as usize).min(PUPIL_RINGS - 1);
+
+        let bounds = self.exit_pupils[ring];
+        let p = bounds.lerp(u);
+
+        // the bounds were found along +x, rotate them to the film point
+        let (sin, cos) = if r > 0. { (film.y / r, film.x / r) } else { (0., 1.) };
+        (Point3D::new(cos * p.x - sin * p.y, sin * p.x + cos * p.y, self.rear_z()), bounds.area())
+    }
+}
+
+impl CameraModel for RealisticCamera {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        self.sample_ray(u, v, Point2D::new(0.5, 0.5)).map(|(ray, _)| ray)
+    }
+
+    /// The weight is cos^4 of the angle leaving the film times the sampled pupil area, relative to the film center.
+    fn sample_ray(&self, u: f32, v: f32, lens: Point2D) -> Option<(Ray, f32)> {
+        // the lens flips the image, the top of the film sees the bottom of the scene
+        let film = Point3D::new((0.5 - u) * self.film_width, (v - 0.5) * self.film_height, 0.);
+
+        let (rear, area) = self.sample_exit_pupil(film, lens);
+        let d = rear - film;
+
+        let (o, dir) = self.trace_from_film(film, d)?;
+
+        let cos_theta = d.normalized().z;
+        let weight = cos_theta.powi(4) * area / self.exit_pupils[0].area();
+
+        let origin = self.view.origin + self.view.direction(o.x, o.y, o.z);
+        let dir = self.view.direction(dir.x, dir.y, dir.z).normalized();
+        Some((Ray::new(&origin, &dir), weight))
+    }
+
+    fn view(&self) -> View {
+        self.view
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("RealisticCamera");
+        self.view.fingerprint(h);
+        for i in &self.interfaces {
+            h.write_f32(i.radius);
+            h.write_f32(i.thickness);
+            h.write_f32(i.ior);
+            h.write_f32(i.aperture_radius);
+        }
+        h.write_f32(self.film_width);
+        h.write_f32(self.film_height);
+    }
+}
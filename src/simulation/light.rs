@@ -0,0 +1,85 @@
+use crate::math::vector::{Point2D, Vector3};
+use crate::simulation::hittable::{HitRecord, HittableList};
+use crate::simulation::spectrum::Wavelength;
+
+/// Point sampled on an area light.
+pub struct LightSample {
+    /// Recorded as seen from outside, so the material's `emitted` gives what the front side emits.
+    pub rec: HitRecord,
+    /// Normal on the emitting side.
+    pub normal: Vector3,
+    /// Density over the surface of all lights, including the choice of the light.
+    pub pdf_area: f32
+}
+
+/// The emissive objects of a world, sampled as area lights for next event estimation and light
+/// paths. Lights are chosen uniformly and points uniformly over their surface. Area lights emit
+/// from their front side only, as `Principled` emission does. The background is not included.
+#[derive(Default)]
+pub struct LightList {
+    /// Object ids of the lights, `HitRecord::object_id` numbering.
+    lights: Vec<u32>,
+    /// Surface area per object id, 0 for objects that are no lights.
+    areas: Vec<f32>
+}
+
+#[allow(dead_code)]
+impl LightList {
+    pub fn new(world: &HittableList) -> Self {
+        let mut list = Self::default();
+        list.areas.push(0.);
+
+        for (i, object) in world.objects().iter().enumerate() {
+            let area = object.area();
+            if object.is_emissive() && area > 0. {
+                list.lights.push(i as u32 + 1);
+                list.areas.push(area);
+            } else {
+                list.areas.push(0.);
+            }
+        }
+
+        list
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// Probability of choosing the light with `object_id`, 0 when it is no light.
+    pub fn pmf(&self, object_id: u32) -> f32 {
+        if self.is_light(object_id) { 1. / self.lights.len() as f32 } else { 0. }
+    }
+
+    pub fn is_light(&self, object_id: u32) -> bool {
+        self.areas.get(object_id as usize).is_some_and(|&a| a > 0.)
+    }
+
+    /// Density over the lights' surfaces of sampling a point on the light with `object_id`.
+    pub fn pdf_area(&self, object_id: u32) -> f32 {
+        if !self.is_light(object_id) {
+            return 0.;
+        }
+
+        self.pmf(object_id) / self.areas[object_id as usize]
+    }
+
+    /// Chooses a light with `uc` and a point on it with `u`.
+    pub fn sample(&self, world: &HittableList, uc: f32, u: Point2D, wavelength: Option<Wavelength>) -> Option<LightSample> {
+        if self.lights.is_empty() {
+            return None;
+        }
+
+        let index = ((uc * self.lights.len() as f32) as usize).min(self.lights.len() - 1);
+        let object_id = self.lights[index];
+
+        let mut rec = world.objects()[object_id as usize - 1].sample_surface(u, wavelength)?;
+        rec.set_object_id(object_id);
+
+        Some(LightSample { normal: rec.geometric_normal(), pdf_area: self.pdf_area(object_id), rec })
+    }
+}
@@ -0,0 +1,185 @@
+use std::sync::Arc;
+
+use crate::math::vector::Color;
+use crate::simulation::bsdf::{Bsdf, ConductorBxdf, DielectricBxdf, DiffuseBxdf};
+use crate::simulation::checkpoint::SceneHasher;
+use crate::simulation::hittable::HitRecord;
+use crate::simulation::microfacet::{self, Metal, TrowbridgeReitz};
+use crate::simulation::spectrum::{self, Ior};
+use crate::simulation::texture::{SolidColor, Texture};
+
+pub trait Material: Send + Sync {
+    fn bsdf(&self, rec: &HitRecord) -> Bsdf;
+
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::zero()
+    }
+
+    /// Whether `emitted` can be non-zero anywhere, objects with such materials are sampled as lights.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Overall reflectance in the working space, for the albedo AOV and denoising.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::new(1., 1., 1.)
+    }
+
+    /// Hashes what affects the rendered image, see `SceneHasher`.
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str(std::any::type_name::<Self>());
+    }
+}
+
+pub struct Lambertian {
+    albedo: Arc<dyn Texture>
+}
+
+/// Rough metal, Cook-Torrance with GGX and complex Fresnel.
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    roughness_u: f32,
+    roughness_v: f32
+}
+
+/// Smooth or rough glass with reflection and transmission.
+pub struct Dielectric {
+    ior: Ior,
+    roughness_u: f32,
+    roughness_v: f32
+}
+
+fn distribution(roughness_u: f32, roughness_v: f32) -> TrowbridgeReitz {
+    TrowbridgeReitz::new(
+        TrowbridgeReitz::roughness_to_alpha(roughness_u),
+        TrowbridgeReitz::roughness_to_alpha(roughness_v)
+    )
+}
+
+#[allow(dead_code)]
+impl Lambertian {
+    pub fn new(albedo: Color) -> Self {
+        Self { albedo: Arc::new(SolidColor::new(albedo)) }
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Lambertian {
+    fn bsdf(&self, rec: &HitRecord) -> Bsdf {
+        let r = spectrum::at_wavelength(self.albedo.value(rec.uv(), rec.p()), rec.wavelength());
+        Bsdf::new(rec.shading_frame(), Box::new(DiffuseBxdf::new(r)))
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value(rec.uv(), rec.p())
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("Lambertian");
+        self.albedo.fingerprint(h);
+    }
+}
+
+#[allow(dead_code)]
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f32) -> Self {
+        Self { eta, k, roughness_u: roughness, roughness_v: roughness }
+    }
+
+    pub fn metal(metal: Metal, roughness: f32) -> Self {
+        Self::new(metal.eta(), metal.k(), roughness)
+    }
+
+    pub fn gold(roughness: f32) -> Self {
+        Self::metal(Metal::Gold, roughness)
+    }
+
+    pub fn copper(roughness: f32) -> Self {
+        Self::metal(Metal::Copper, roughness)
+    }
+
+    pub fn aluminum(roughness: f32) -> Self {
+        Self::metal(Metal::Aluminum, roughness)
+    }
+
+    /// Different roughness along the surface tangent (`dpdu`) and bitangent, e.g. brushed metal.
+    pub fn with_anisotropy(mut self, roughness_u: f32, roughness_v: f32) -> Self {
+        self.roughness_u = roughness_u;
+        self.roughness_v = roughness_v;
+        self
+    }
+}
+
+impl Material for Conductor {
+    fn bsdf(&self, rec: &HitRecord) -> Bsdf {
+        let distrib = distribution(self.roughness_u, self.roughness_v);
+
+        let (eta, k) = match rec.wavelength() {
+            Some(w) => {
+                let eta = spectrum::rgb_curve(self.eta, w.lambda);
+                let k = spectrum::rgb_curve(self.k, w.lambda);
+                (Color::new(eta, eta, eta), Color::new(k, k, k))
+            }
+            None => (self.eta, self.k)
+        };
+
+        Bsdf::new(rec.shading_frame(), Box::new(ConductorBxdf::new(distrib, eta, k)))
+    }
+
+    /// Reflectance at normal incidence.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        microfacet::fresnel_complex(1., self.eta, self.k)
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("Conductor");
+        h.write_vector(self.eta);
+        h.write_vector(self.k);
+        h.write_f32(self.roughness_u);
+        h.write_f32(self.roughness_v);
+    }
+}
+
+#[allow(dead_code)]
+impl Dielectric {
+    pub fn new(eta: f32, roughness: f32) -> Self {
+        Self::dispersive(Ior::Constant(eta), roughness)
+    }
+
+    /// IOR varying with wavelength, splits light into colors when rendering spectrally.
+    pub fn dispersive(ior: Ior, roughness: f32) -> Self {
+        Self { ior, roughness_u: roughness, roughness_v: roughness }
+    }
+
+    pub fn glass(roughness: f32) -> Self {
+        Self::new(1.5, roughness)
+    }
+
+    pub fn with_anisotropy(mut self, roughness_u: f32, roughness_v: f32) -> Self {
+        self.roughness_u = roughness_u;
+        self.roughness_v = roughness_v;
+        self
+    }
+}
+
+impl Material for Dielectric {
+    fn bsdf(&self, rec: &HitRecord) -> Bsdf {
+        let distrib = distribution(self.roughness_u, self.roughness_v);
+        let eta = self.ior.at(rec.wavelength().map(|w| w.lambda));
+        // the shading normal faces the incoming ray, so leaving the object flips the ratio
+        let eta = if rec.front_face() { eta } else { 1. / eta };
+
+        Bsdf::new(rec.shading_frame(), Box::new(DielectricBxdf::new(distrib, eta)))
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("Dielectric");
+        self.ior.fingerprint(h);
+        h.write_f32(self.roughness_u);
+        h.write_f32(self.roughness_v);
+    }
+}
@@ -0,0 +1,264 @@
+use std::f32::consts::PI;
+
+use crate::math::vector::{Color, Point2D, Vector3};
+
+/// Trowbridge-Reitz (GGX) microfacet distribution with anisotropic roughness.
+/// Directions are in the local shading frame, +z is the macro surface normal.
+#[derive(Copy, Clone)]
+pub struct TrowbridgeReitz {
+    alpha_x: f32,
+    alpha_y: f32
+}
+
+#[allow(dead_code)]
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+
+    /// Perceptual roughness in [0, 1] to GGX alpha.
+    pub fn roughness_to_alpha(roughness: f32) -> f32 {
+        roughness * roughness
+    }
+
+    pub fn alpha_x(&self) -> f32 {
+        self.alpha_x
+    }
+
+    pub fn alpha_y(&self) -> f32 {
+        self.alpha_y
+    }
+
+    /// Below this the lobe is treated as a perfect mirror.
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    /// Narrow enough that what the lobe reflects stays recognizable, up to roughness about 0.2.
+    pub fn is_glossy(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 0.05
+    }
+
+    pub fn d(&self, wm: Vector3) -> f32 {
+        let x = wm.x / self.alpha_x;
+        let y = wm.y / self.alpha_y;
+        let e = x * x + y * y + wm.z * wm.z;
+
+        1. / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    pub fn lambda(&self, w: Vector3) -> f32 {
+        let z2 = w.z * w.z;
+        if z2 == 0. {
+            return f32::INFINITY;
+        }
+
+        let alpha2_tan2 = (self.alpha_x * self.alpha_x * w.x * w.x + self.alpha_y * self.alpha_y * w.y * w.y) / z2;
+        ((1. + alpha2_tan2).sqrt() - 1.) * 0.5
+    }
+
+    pub fn g1(&self, w: Vector3) -> f32 {
+        1. / (1. + self.lambda(w))
+    }
+
+    /// Smith height-correlated masking-shadowing.
+    pub fn g(&self, wo: Vector3, wi: Vector3) -> f32 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Distribution of normals visible from `w`.
+    pub fn d_visible(&self, w: Vector3, wm: Vector3) -> f32 {
+        if w.z == 0. {
+            return 0.;
+        }
+
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    pub fn pdf(&self, w: Vector3, wm: Vector3) -> f32 {
+        self.d_visible(w, wm)
+    }
+
+    /// Samples a visible normal, Heitz 2018 "Sampling the GGX Distribution of Visible Normals".
+    pub fn sample_wm(&self, w: Vector3, u: Point2D) -> Vector3 {
+        let mut wh = Vector3::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalized();
+        if wh.z < 0. {
+            wh = -wh;
+        }
+
+        let t1 = if wh.z < 0.99999 {
+            Vector3::new(0., 0., 1.).cross(wh).normalized()
+        } else {
+            Vector3::new(1., 0., 0.)
+        };
+        let t2 = wh.cross(t1);
+
+        let r = u.x.sqrt();
+        let phi = 2. * PI * u.y;
+        let px = r * phi.cos();
+        let mut py = r * phi.sin();
+
+        // warp the disk to the projected hemisphere seen from `w`
+        let h = (1. - px * px).max(0.).sqrt();
+        let s = (1. + wh.z) * 0.5;
+        py = (1. - s) * h + s * py;
+
+        let pz = (1. - px * px - py * py).max(0.).sqrt();
+        let nh = t1 * px + t2 * py + wh * pz;
+
+        Vector3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalized()
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Complex {
+    re: f32,
+    im: f32
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    fn add(self, o: Self) -> Self {
+        Self::new(self.re + o.re, self.im + o.im)
+    }
+
+    fn sub(self, o: Self) -> Self {
+        Self::new(self.re - o.re, self.im - o.im)
+    }
+
+    fn mul(self, o: Self) -> Self {
+        Self::new(self.re * o.re - self.im * o.im, self.re * o.im + self.im * o.re)
+    }
+
+    fn div(self, o: Self) -> Self {
+        let scale = 1. / (o.re * o.re + o.im * o.im);
+        Self::new(
+            scale * (self.re * o.re + self.im * o.im),
+            scale * (self.im * o.re - self.re * o.im)
+        )
+    }
+
+    fn norm(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn sqrt(self) -> Self {
+        let n = self.norm().sqrt();
+        if n == 0. {
+            return Self::new(0., 0.);
+        }
+
+        let t1 = (0.5 * (n + self.re.abs())).sqrt();
+        let t2 = 0.5 * self.im / t1;
+
+        if self.re >= 0. {
+            Self::new(t1, t2)
+        } else {
+            Self::new(t2.abs(), t1.copysign(self.im))
+        }
+    }
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface, `eta` = transmitted / incident IOR.
+pub fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let mut cos_i = cos_theta_i.clamp(-1., 1.);
+    let mut eta = eta;
+
+    if cos_i < 0. {
+        eta = 1. / eta;
+        cos_i = -cos_i;
+    }
+
+    let sin2_i = 1. - cos_i * cos_i;
+    let sin2_t = sin2_i / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+
+    let cos_t = (1. - sin2_t).max(0.).sqrt();
+
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+
+    (r_parl * r_parl + r_perp * r_perp) * 0.5
+}
+
+fn fresnel_complex_1(cos_theta_i: f32, eta: Complex) -> f32 {
+    let cos_i = Complex::new(cos_theta_i.clamp(0., 1.), 0.);
+    let sin2_i = Complex::new(1. - cos_i.re * cos_i.re, 0.);
+    let sin2_t = sin2_i.div(eta.mul(eta));
+    let cos_t = Complex::new(1., 0.).sub(sin2_t).sqrt();
+
+    let r_parl = eta.mul(cos_i).sub(cos_t).div(eta.mul(cos_i).add(cos_t));
+    let r_perp = cos_i.sub(eta.mul(cos_t)).div(cos_i.add(eta.mul(cos_t)));
+
+    (r_parl.norm() + r_perp.norm()) * 0.5
+}
+
+/// Fresnel reflectance of a conductor with complex IOR `eta + i k`, per color channel.
+pub fn fresnel_complex(cos_theta_i: f32, eta: Color, k: Color) -> Color {
+    Color::new(
+        fresnel_complex_1(cos_theta_i, Complex::new(eta.x, k.x)),
+        fresnel_complex_1(cos_theta_i, Complex::new(eta.y, k.y)),
+        fresnel_complex_1(cos_theta_i, Complex::new(eta.z, k.z))
+    )
+}
+
+pub fn reflect(wo: Vector3, n: Vector3) -> Vector3 {
+    -wo + n * (2. * wo.dot(n))
+}
+
+/// Refracts `wi` through the interface with normal `n`, `eta` = transmitted / incident IOR
+/// relative to the side `n` points to. Returns the direction and the effective eta, None on total internal reflection.
+pub fn refract(wi: Vector3, n: Vector3, eta: f32) -> Option<(Vector3, f32)> {
+    let mut cos_i = n.dot(wi);
+    let mut eta = eta;
+    let mut n = n;
+
+    if cos_i < 0. {
+        eta = 1. / eta;
+        cos_i = -cos_i;
+        n = -n;
+    }
+
+    let sin2_i = (1. - cos_i * cos_i).max(0.);
+    let sin2_t = sin2_i / (eta * eta);
+    if sin2_t >= 1. {
+        return None;
+    }
+
+    let cos_t = (1. - sin2_t).sqrt();
+
+    Some((-wi / eta + n * (cos_i / eta - cos_t), eta))
+}
+
+/// Measured complex IOR of common metals at roughly 650 / 550 / 450 nm.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum Metal {
+    Gold,
+    Copper,
+    Aluminum
+}
+
+#[allow(dead_code)]
+impl Metal {
+    pub fn eta(&self) -> Color {
+        match self {
+            Metal::Gold => Color::new(0.143, 0.374, 1.442),
+            Metal::Copper => Color::new(0.200, 0.924, 1.102),
+            Metal::Aluminum => Color::new(1.657, 0.880, 0.521)
+        }
+    }
+
+    pub fn k(&self) -> Color {
+        match self {
+            Metal::Gold => Color::new(3.983, 2.385, 1.603),
+            Metal::Copper => Color::new(3.912, 2.452, 2.142),
+            Metal::Aluminum => Color::new(9.224, 6.270, 4.837)
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::f32::consts::PI;
+
+use crate::simulation::sampler::{RandomSampler, Sampler};
+
+/// Settings of primary sample space Metropolis light transport (Kelemen et al. 2002). Instead of
+/// sampling pixels one by one, `chains` Markov chains wander over the random numbers the pixel
+/// function consumes, the first two choosing the film position, and splat what they find with
+/// density proportional to its brightness. A bootstrap phase of `bootstrap` independent samples
+/// estimates the image brightness the chains are normalized to and picks their starting points.
+#[derive(Copy, Clone, Debug)]
+pub struct Mlt {
+    /// Mutations per image pixel over all chains.
+    pub mutations_per_pixel: i32,
+    pub bootstrap: usize,
+    pub chains: usize,
+    /// Probability of a large step, replacing all random numbers by fresh ones.
+    pub large_step_probability: f32,
+    /// Standard deviation of the small step perturbing each random number.
+    pub sigma: f32
+}
+
+#[allow(dead_code)]
+impl Mlt {
+    pub fn new(mutations_per_pixel: i32) -> Self {
+        Self {
+            mutations_per_pixel: mutations_per_pixel.max(1),
+            bootstrap: 100_000,
+            chains: 1000,
+            large_step_probability: 0.3,
+            sigma: 0.01
+        }
+    }
+
+    pub fn with_bootstrap(mut self, bootstrap: usize) -> Self {
+        self.bootstrap = bootstrap.max(1);
+        self
+    }
+
+    pub fn with_chains(mut self, chains: usize) -> Self {
+        self.chains = chains.max(1);
+        self
+    }
+
+    pub fn with_large_step_probability(mut self, probability: f32) -> Self {
+        self.large_step_probability = probability.clamp(0., 1.);
+        self
+    }
+
+    pub fn with_sigma(mut self, sigma: f32) -> Self {
+        self.sigma = sigma;
+        self
+    }
+}
+
+/// One random number of the current state, with what it was before the proposed mutation.
+#[derive(Copy, Clone, Default)]
+struct PrimarySample {
+    value: f32,
+    /// Iteration that last changed the value.
+    modified: u64,
+    value_backup: f32,
+    modified_backup: u64
+}
+
+/// Sampler whose numbers are the state of a Markov chain. Numbers are mutated lazily when the
+/// pixel function asks for them, catching up on the small steps they missed, so paths may use
+/// any number of dimensions. Every iteration starts with `start_iteration` and ends with either
+/// `accept` or `reject`, which restores the numbers the proposal changed.
+pub struct MltSampler {
+    rng: RandomSampler,
+    sigma: f32,
+    large_step_probability: f32,
+    samples: Vec<PrimarySample>,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    index: usize
+}
+
+#[allow(dead_code)]
+impl MltSampler {
+    /// The first iteration draws all numbers at random, so a chain started with the same `seed`
+    /// replays a bootstrap sample.
+    pub fn new(seed: u64, sigma: f32, large_step_probability: f32) -> Self {
+        Self {
+            rng: RandomSampler::new(seed, 0x6d6c74),
+            sigma,
+            large_step_probability,
+            samples: Vec::new(),
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            index: 0
+        }
+    }
+
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.next_1d() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    pub fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.modified == self.iteration {
+                sample.value = sample.value_backup;
+                sample.modified = sample.modified_backup;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    pub fn is_large_step(&self) -> bool {
+        self.large_step
+    }
+
+    /// Standard normal number by the Box-Muller transform.
+    fn normal(&mut self) -> f32 {
+        let u1 = 1. - self.rng.next_1d();
+        let u2 = self.rng.next_1d();
+        (-2. * u1.ln()).sqrt() * (2. * PI * u2).cos()
+    }
+
+    /// Brings number `index` up to the current iteration.
+    fn mutate(&mut self, index: usize) {
+        if index >= self.samples.len() {
+            self.samples.resize(index + 1, PrimarySample::default());
+        }
+
+        // numbers first used after the last large step were never drawn for it
+        if self.samples[index].modified < self.last_large_step {
+            self.samples[index].value = self.rng.next_1d();
+            self.samples[index].modified = self.last_large_step;
+        }
+
+        let sample = self.samples[index];
+        let value = if self.large_step {
+            self.rng.next_1d()
+        } else {
+            // the sum of the missed small steps is normal with the variance of all of them
+            let steps = (self.iteration - sample.modified) as f32;
+            let v = sample.value + self.normal() * self.sigma * steps.sqrt();
+            v - v.floor()
+        };
+
+        self.samples[index] = PrimarySample {
+            // wrapping may round up to exactly 1
+            value: if value < 1. { value } else { 0. },
+            modified: self.iteration,
+            value_backup: sample.value,
+            modified_backup: sample.modified
+        };
+    }
+}
+
+impl Sampler for MltSampler {
+    fn next_1d(&mut self) -> f32 {
+        let index = self.index;
+        self.index += 1;
+
+        self.mutate(index);
+        self.samples[index].value
+    }
+}
@@ -0,0 +1,512 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f32::consts::PI;
+use std::ops::Range;
+use std::sync::OnceLock;
+use std::thread;
+
+use crate::math::sampling;
+use crate::math::vector::{Color, Onb, Point3D, Vector3};
+use crate::simulation::aov::Aov;
+use crate::simulation::bsdf::{Bsdf, DiffuseBxdf};
+use crate::simulation::engine::PixelContext;
+use crate::simulation::hittable::{HitRecord, Hittable, HittableList};
+use crate::simulation::light::LightList;
+use crate::simulation::ray::Ray;
+use crate::simulation::sampler::{RandomSampler, Sampler};
+
+/// Ray extent, the same the path tracer uses.
+const T_MIN: f32 = 0.001;
+const T_MAX: f32 = 1000.;
+
+/// Flux arriving at a surface point.
+#[derive(Copy, Clone)]
+pub struct Photon {
+    pub p: Point3D,
+    /// Towards where the photon came from.
+    pub wi: Vector3,
+    /// Geometric normal on the side the photon arrived at.
+    pub normal: Vector3,
+    pub power: Color
+}
+
+fn coordinate(p: Point3D, axis: u8) -> f32 {
+    match axis {
+        0 => p.x,
+        1 => p.y,
+        _ => p.z
+    }
+}
+
+/// Photons in a balanced kd-tree, stored implicitly: the median of every range is the node
+/// splitting it, along the axis of largest extent.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<u8>
+}
+
+/// Photon found by a lookup, ordered by distance so the heap keeps the farthest on top.
+struct Candidate {
+    dist2: f32,
+    index: usize
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.dist2 == other.dist2
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.dist2.total_cmp(&other.dist2)
+    }
+}
+
+#[allow(dead_code)]
+impl PhotonMap {
+    /// Builds the tree on up to `threads` threads.
+    pub fn new(mut photons: Vec<Photon>, threads: usize) -> Self {
+        let mut axes = vec![0; photons.len()];
+        Self::build(&mut photons, &mut axes, threads);
+        Self { photons, axes }
+    }
+
+    fn build(photons: &mut [Photon], axes: &mut [u8], threads: usize) {
+        if photons.len() <= 1 {
+            return;
+        }
+
+        let mut min = photons[0].p;
+        let mut max = photons[0].p;
+        for photon in photons.iter() {
+            min = Vector3::new(min.x.min(photon.p.x), min.y.min(photon.p.y), min.z.min(photon.p.z));
+            max = Vector3::new(max.x.max(photon.p.x), max.y.max(photon.p.y), max.z.max(photon.p.z));
+        }
+        let extent = max - min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        let mid = photons.len() / 2;
+        photons.select_nth_unstable_by(mid, |a, b| coordinate(a.p, axis).total_cmp(&coordinate(b.p, axis)));
+        axes[mid] = axis;
+
+        let (left, rest) = photons.split_at_mut(mid);
+        let (left_axes, rest_axes) = axes.split_at_mut(mid);
+        let (right, right_axes) = (&mut rest[1..], &mut rest_axes[1..]);
+
+        // the halves are independent, split the threads among them
+        if threads > 1 {
+            thread::scope(|s| {
+                s.spawn(|| Self::build(left, left_axes, threads / 2));
+                Self::build(right, right_axes, threads - threads / 2);
+            });
+        } else {
+            Self::build(left, left_axes, 1);
+            Self::build(right, right_axes, 1);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Up to `k` photons nearest to `p` within `max_radius`, and the squared radius of the disc
+    /// they were gathered from: the distance to the farthest when `k` were found.
+    pub fn nearest(&self, p: Point3D, k: usize, max_radius: f32) -> (Vec<&Photon>, f32) {
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        let mut radius2 = max_radius * max_radius;
+
+        self.search(0, self.photons.len(), p, k, &mut heap, &mut radius2);
+
+        (heap.into_iter().map(|c| &self.photons[c.index]).collect(), radius2)
+    }
+
+    fn search(&self, lo: usize, hi: usize, p: Point3D, k: usize, heap: &mut BinaryHeap<Candidate>, radius2: &mut f32) {
+        if lo >= hi || k == 0 {
+            return;
+        }
+
+        let mid = lo + (hi - lo) / 2;
+        let photon = &self.photons[mid];
+        let axis = self.axes[mid];
+
+        let delta = coordinate(p, axis) - coordinate(photon.p, axis);
+        let (near, far) = if delta < 0. { ((lo, mid), (mid + 1, hi)) } else { ((mid + 1, hi), (lo, mid)) };
+
+        self.search(near.0, near.1, p, k, heap, radius2);
+
+        let dist2 = (photon.p - p).magnitude_squared();
+        if dist2 < *radius2 {
+            heap.push(Candidate { dist2, index: mid });
+            if heap.len() > k {
+                heap.pop();
+            }
+            if heap.len() == k {
+                *radius2 = heap.peek().map_or(*radius2, |c| c.dist2);
+            }
+        }
+
+        if delta * delta < *radius2 {
+            self.search(far.0, far.1, p, k, heap, radius2);
+        }
+    }
+
+    /// Radiance reflected towards `wo` at `rec`, from the flux of the nearby photons per disc area.
+    /// Photons that arrived at the other side of the surface are left out.
+    fn estimate(&self, rec: &HitRecord, bsdf: &Bsdf, wo: Vector3, k: usize, max_radius: f32) -> Color {
+        if self.photons.is_empty() {
+            return Color::zero();
+        }
+
+        let (photons, radius2) = self.nearest(rec.p(), k, max_radius);
+
+        let mut flux = Color::zero();
+        for photon in photons {
+            if photon.normal.dot(rec.geometric_normal()) > 0. {
+                flux += bsdf.f(wo, photon.wi) * photon.power;
+            }
+        }
+
+        flux / (PI * radius2)
+    }
+}
+
+struct PhotonMaps {
+    /// Photons that reached a diffuse surface only through specular or glossy bounces.
+    caustic: PhotonMap,
+    /// All photons stored on diffuse surfaces.
+    global: PhotonMap
+}
+
+/// Two pass photon mapping (Jensen 1996). Photons from the area lights are traced through the
+/// scene first and stored on diffuse surfaces, in a caustic map for those that came through
+/// specular or glossy bounces only and a global map for all. Surfaces count as diffuse unless
+/// `Bsdf::is_glossy`. Rendering follows glossy bounces from the camera, then at the first diffuse
+/// surface adds direct light by light sampling, caustics from the caustic map and indirect light
+/// by a final gather ray reading the global map where it lands. Photons are traced in RGB. The
+/// background lights only directly, it emits no photons.
+pub struct PhotonMapping {
+    /// Number of photons emitted.
+    photons: usize,
+    caustic_lookup: usize,
+    caustic_radius: f32,
+    global_lookup: usize,
+    global_radius: f32,
+    seed: u64,
+    maps: OnceLock<PhotonMaps>
+}
+
+/// Bsdf of the hit, objects without material scatter like grey diffuse surfaces.
+fn surface_bsdf(rec: &HitRecord) -> Bsdf {
+    match rec.material() {
+        Some(material) => material.bsdf(rec),
+        None => Bsdf::new(rec.shading_frame(), Box::new(DiffuseBxdf::new(Color::new(0.5, 0.5, 0.5))))
+    }
+}
+
+fn max_component(c: Color) -> f32 {
+    c.x.max(c.y).max(c.z)
+}
+
+/// Adds light of a path with `depth` bounces, split into the lighting AOVs.
+fn contribute(ctx: &mut PixelContext, depth: i32, light: Color, radiance: &mut Color) {
+    let light = ctx.clamp_light(depth, light);
+    *radiance += light;
+
+    let aov = match depth {
+        0 => Aov::Emission,
+        1 => Aov::Direct,
+        _ => Aov::Indirect
+    };
+    ctx.aov.add(aov, light);
+}
+
+#[allow(dead_code)]
+impl PhotonMapping {
+    /// Emits `photons` photons, gathering up to 50 within 0.05 for caustics and 100 within 0.25
+    /// for indirect light.
+    pub fn new(photons: usize) -> Self {
+        Self {
+            photons,
+            caustic_lookup: 50,
+            caustic_radius: 0.05,
+            global_lookup: 100,
+            global_radius: 0.25,
+            seed: 0,
+            maps: OnceLock::new()
+        }
+    }
+
+    /// Number of photons and largest radius of the caustic density estimate. Smaller radii keep caustics sharper, more photons less noisy.
+    pub fn with_caustic_lookup(mut self, photons: usize, max_radius: f32) -> Self {
+        self.caustic_lookup = photons;
+        self.caustic_radius = max_radius;
+        self
+    }
+
+    /// Number of photons and largest radius of the global density estimate read by final gathering.
+    pub fn with_global_lookup(mut self, photons: usize, max_radius: f32) -> Self {
+        self.global_lookup = photons;
+        self.global_radius = max_radius;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Photons stored in the caustic and the global map, None before `trace`.
+    pub fn stored(&self) -> Option<(usize, usize)> {
+        self.maps.get().map(|maps| (maps.caustic.len(), maps.global.len()))
+    }
+
+    /// Traces the photons from the lights of the complete `world` and builds the maps, on `threads`
+    /// threads. Must run once before rendering, later calls keep the first maps. Photon paths
+    /// have at most `trace` rays like the camera paths.
+    pub fn trace(&self, world: &HittableList, trace: i32, threads: usize) {
+        let lights = LightList::new(world);
+        let threads = threads.max(1);
+        let chunk = self.photons.div_ceil(threads);
+
+        let (caustic, global) = thread::scope(|s| {
+            let workers: Vec<_> = (0..threads)
+                .map(|k| {
+                    let lights = &lights;
+                    let range = (k * chunk).min(self.photons)..((k + 1) * chunk).min(self.photons);
+                    s.spawn(move || self.trace_photons(world, lights, trace, range))
+                })
+                .collect();
+
+            let mut caustic = Vec::new();
+            let mut global = Vec::new();
+            for worker in workers {
+                let (c, g) = worker.join().unwrap();
+                caustic.extend(c);
+                global.extend(g);
+            }
+            (caustic, global)
+        });
+
+        let (caustic, global) = thread::scope(|s| {
+            let caustic = s.spawn(|| PhotonMap::new(caustic, (threads / 2).max(1)));
+            let global = PhotonMap::new(global, threads - threads / 2);
+            (caustic.join().unwrap(), global)
+        });
+
+        let _ = self.maps.set(PhotonMaps { caustic, global });
+    }
+
+    /// Traces the photons numbered `range`, each from its own random stream so the maps do not
+    /// depend on the number of threads. Russian roulette keeps their power even.
+    fn trace_photons(&self, world: &HittableList, lights: &LightList, trace: i32, range: Range<usize>) -> (Vec<Photon>, Vec<Photon>) {
+        let mut caustic = Vec::new();
+        let mut global = Vec::new();
+
+        for i in range {
+            let mut sampler = RandomSampler::new(self.seed, i as u64);
+            let uc = sampler.next_1d();
+            let u = sampler.next_2d();
+            let Some(ls) = lights.sample(world, uc, u, None) else {
+                break;
+            };
+
+            let local = sampling::cosine_hemisphere(sampler.next_2d());
+            let le = ls.rec.material().map_or(Color::zero(), |m| m.emitted(&ls.rec));
+            if local.z <= 0. || ls.pdf_area <= 0. {
+                continue;
+            }
+
+            // Le cos / (pdf_area cos / pi), shared by all photons
+            let mut power = le * (PI / (ls.pdf_area * self.photons as f32));
+            let mut ray = Ray::new(&ls.rec.p(), &Onb::from_w(ls.normal).to_world(local));
+            let mut caustic_path = true;
+
+            for bounce in 0..trace {
+                let Some(rec) = world.hit(&ray, T_MIN, T_MAX) else {
+                    break;
+                };
+
+                let bsdf = surface_bsdf(&rec);
+                let wo = -ray.direction().normalized();
+
+                if !bsdf.is_glossy() {
+                    let photon = Photon { p: rec.p(), wi: wo, normal: rec.geometric_normal(), power };
+                    if caustic_path && bounce > 0 {
+                        caustic.push(photon);
+                    }
+                    global.push(photon);
+                }
+
+                let Some(s) = bsdf.sample_f(wo, sampler.next_1d(), sampler.next_2d()) else {
+                    break;
+                };
+                caustic_path &= s.specular || bsdf.is_glossy();
+
+                // flux is not compressed by refraction, undo the radiance scaling of `f`
+                let scattered = power * s.f * (s.wi.dot(rec.normal()).abs() / s.pdf * s.eta * s.eta);
+                let survive = (max_component(scattered) / max_component(power)).min(1.);
+                if survive <= 0. || sampler.next_1d() >= survive {
+                    break;
+                }
+
+                power = scattered / survive;
+                ray = ray.spawn(&rec.p(), &s.wi);
+            }
+        }
+
+        (caustic, global)
+    }
+
+    /// Light from one sampled point on the area lights reflected towards `wo`.
+    fn direct(&self, world: &HittableList, ctx: &mut PixelContext, rec: &HitRecord, bsdf: &Bsdf, wo: Vector3) -> Color {
+        let uc = ctx.sampler.next_1d();
+        let u = ctx.sampler.next_2d();
+        let Some(ls) = ctx.lights.sample(world, uc, u, rec.wavelength()) else {
+            return Color::zero();
+        };
+
+        let d = ls.rec.p() - rec.p();
+        let dist2 = d.magnitude_squared();
+        let dist = dist2.sqrt();
+        let wi = d / dist;
+
+        let cos_light = ls.normal.dot(-wi);
+        if cos_light <= 0. || dist <= 2. * T_MIN {
+            return Color::zero();
+        }
+
+        let f = bsdf.f(wo, wi) * wi.dot(rec.normal()).abs();
+        if max_component(f) <= 0. {
+            return Color::zero();
+        }
+
+        ctx.rays += 1;
+        if world.hit(&Ray::new(&rec.p(), &wi), T_MIN, dist - T_MIN).is_some() {
+            return Color::zero();
+        }
+
+        let le = ls.rec.material().map_or(Color::zero(), |m| m.emitted(&ls.rec));
+        f * le * (cos_light / (ls.pdf_area * dist2))
+    }
+
+    /// Indirect light by one gather ray, read from the global map at the first diffuse surface it
+    /// reaches. Emitters found are skipped, direct light and caustics account for them.
+    #[allow(clippy::too_many_arguments)]
+    fn gather(&self, world: &HittableList, ctx: &mut PixelContext, maps: &PhotonMaps, rec: &HitRecord, bsdf: &Bsdf, wo: Vector3, background: &dyn Fn(&Ray) -> Color, bounces: i32) -> Color {
+        let uc = ctx.sampler.next_1d();
+        let u = ctx.sampler.next_2d();
+        let Some(s) = bsdf.sample_f(wo, uc, u) else {
+            return Color::zero();
+        };
+        if s.specular {
+            // the camera path follows specular lobes itself
+            return Color::zero();
+        }
+
+        let mut throughput = s.f * (s.wi.dot(rec.normal()).abs() / s.pdf);
+        let mut ray = Ray::new(&rec.p(), &s.wi).with_wavelength(rec.wavelength());
+
+        for _ in 0..bounces {
+            ctx.rays += 1;
+            let Some(hit) = world.hit(&ray, T_MIN, T_MAX) else {
+                return throughput * background(&ray);
+            };
+
+            let hit_bsdf = surface_bsdf(&hit);
+            let hit_wo = -ray.direction().normalized();
+            if !hit_bsdf.is_glossy() {
+                return throughput * maps.global.estimate(&hit, &hit_bsdf, hit_wo, self.global_lookup, self.global_radius);
+            }
+
+            let Some(s) = hit_bsdf.sample_f(hit_wo, ctx.sampler.next_1d(), ctx.sampler.next_2d()) else {
+                break;
+            };
+            throughput = throughput * s.f * (s.wi.dot(hit.normal()).abs() / s.pdf);
+            ray = ray.spawn(&hit.p(), &s.wi);
+        }
+
+        Color::zero()
+    }
+
+    /// Radiance along `ray`. Paths have at most `trace` rays like the path tracer.
+    /// Panics when the photons were not traced yet.
+    pub fn radiance(&self, ray: &Ray, world: &HittableList, ctx: &mut PixelContext, trace: i32, background: &dyn Fn(&Ray) -> Color) -> Color {
+        let maps = self.maps.get().expect("photons are traced before rendering");
+
+        let mut radiance = Color::zero();
+        let mut throughput = Color::new(1., 1., 1.);
+        let mut ray = ray.spawn(&ray.origin(), &ray.direction());
+
+        for bounce in 0..trace {
+            ctx.rays += 1;
+
+            let Some(rec) = world.hit(&ray, T_MIN, T_MAX) else {
+                contribute(ctx, bounce, throughput * background(&ray), &mut radiance);
+                break;
+            };
+
+            if bounce == 0 {
+                ctx.aov.set_scalar(Aov::Depth, rec.t());
+                ctx.aov.set(Aov::Normal, rec.normal());
+                ctx.aov.set(Aov::Position, rec.p());
+                ctx.aov.set_scalar(Aov::ObjectId, rec.object_id() as f32);
+                ctx.aov.set_scalar(Aov::MaterialId, rec.material_id() as f32);
+                if let Some(material) = rec.material() {
+                    ctx.aov.set(Aov::Albedo, material.albedo(&rec));
+                }
+            }
+
+            // every bounce before this one was glossy or specular, light sampling did not find the emitter
+            if let Some(material) = rec.material() {
+                contribute(ctx, bounce, throughput * material.emitted(&rec), &mut radiance);
+            }
+
+            let bsdf = surface_bsdf(&rec);
+            let wo = -ray.direction().normalized();
+
+            let diffuse = !bsdf.is_glossy();
+            if diffuse && bounce + 1 < trace {
+                let direct = self.direct(world, ctx, &rec, &bsdf, wo);
+                contribute(ctx, bounce + 1, throughput * direct, &mut radiance);
+
+                let caustic = maps.caustic.estimate(&rec, &bsdf, wo, self.caustic_lookup, self.caustic_radius);
+                let indirect = self.gather(world, ctx, maps, &rec, &bsdf, wo, background, trace - bounce - 1);
+                contribute(ctx, bounce + 2, throughput * (caustic + indirect), &mut radiance);
+            }
+
+            // on diffuse surfaces only specular lobes continue the path, the rest was estimated above
+            let uc = ctx.sampler.next_1d();
+            let u = ctx.sampler.next_2d();
+            let Some(s) = bsdf.sample_f(wo, uc, u) else {
+                break;
+            };
+            if diffuse && !s.specular {
+                break;
+            }
+
+            throughput = throughput * s.f * (s.wi.dot(rec.normal()).abs() / s.pdf);
+            ray = ray.spawn(&rec.p(), &s.wi);
+        }
+
+        radiance
+    }
+}
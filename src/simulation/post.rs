@@ -0,0 +1,342 @@
+use crate::math::color::ColorSpace;
+use crate::math::noise::hash::Perlin;
+use crate::math::vector::{Color, Vector3};
+
+/// Where the processed image lies in the full frame. Effects tied to the lens, like vignetting,
+/// are centered on the full frame so a region renders the same as its part of the whole image.
+#[derive(Copy, Clone, Debug)]
+pub struct Frame {
+    pub x0: i32,
+    pub y0: i32,
+    pub width: i32,
+    pub height: i32,
+    pub full_width: i32,
+    pub full_height: i32,
+    /// Space the image is in, luminance is measured in it.
+    pub working_space: ColorSpace
+}
+
+impl Frame {
+    fn index(&self, x: i32, y: i32) -> usize {
+        (x + y * self.width) as usize
+    }
+
+    /// Offset of the pixel center from the full frame center, in units of the half diagonal.
+    fn offset_from_center(&self, x: i32, y: i32) -> (f32, f32) {
+        let half_diagonal = 0.5 * ((self.full_width * self.full_width + self.full_height * self.full_height) as f32).sqrt();
+        let dx = (self.x0 + x) as f32 + 0.5 - 0.5 * self.full_width as f32;
+        let dy = (self.y0 + y) as f32 + 0.5 - 0.5 * self.full_height as f32;
+
+        (dx / half_diagonal, dy / half_diagonal)
+    }
+
+    /// Bilinear lookup at continuous pixel coordinates relative to the image, clamped to its border.
+    fn sample(&self, image: &[Color], x: f32, y: f32) -> Color {
+        let x = (x - 0.5).clamp(0., (self.width - 1) as f32);
+        let y = (y - 0.5).clamp(0., (self.height - 1) as f32);
+
+        let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+
+        let top = Color::lerp(image[self.index(x0, y0)], image[self.index(x1, y0)], tx);
+        let bottom = Color::lerp(image[self.index(x0, y1)], image[self.index(x1, y1)], tx);
+        Color::lerp(top, bottom, ty)
+    }
+}
+
+/// One stage of the post-processing stack, working on the linear image before display conversion.
+pub trait PostEffect: Send + Sync {
+    fn apply(&self, image: &mut [Color], frame: &Frame);
+}
+
+/// Post effects applied in the order they were added.
+#[derive(Default)]
+pub struct PostStack {
+    effects: Vec<Box<dyn PostEffect>>
+}
+
+#[allow(dead_code)]
+impl PostStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<E: PostEffect + 'static>(mut self, effect: E) -> Self {
+        self.push(effect);
+        self
+    }
+
+    pub fn push<E: PostEffect + 'static>(&mut self, effect: E) {
+        self.effects.push(Box::new(effect));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    pub fn apply(&self, image: &mut [Color], frame: &Frame) {
+        for effect in &self.effects {
+            effect.apply(image, frame);
+        }
+    }
+}
+
+/// Part of a pixel brighter than `threshold` luminance, the light that blooms.
+fn highlights(image: &[Color], threshold: f32, working_space: ColorSpace) -> Vec<Color> {
+    image.iter().map(|&c| {
+        let l = working_space.luminance(c);
+        if l <= threshold { Color::zero() } else { c * ((l - threshold) / l) }
+    }).collect()
+}
+
+/// Separable gaussian blur with clamped borders.
+fn blur(image: &[Color], frame: &Frame, sigma: f32) -> Vec<Color> {
+    let radius = (3. * sigma).ceil() as i32;
+    let kernel: Vec<f32> = (-radius..=radius).map(|i| (-(i * i) as f32 / (2. * sigma * sigma)).exp()).collect();
+    let norm: f32 = kernel.iter().sum();
+
+    let pass = |src: &[Color], dx: i32, dy: i32| -> Vec<Color> {
+        let mut dst = vec![Color::zero(); src.len()];
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                let mut sum = Color::zero();
+                for (k, w) in kernel.iter().enumerate() {
+                    let o = k as i32 - radius;
+                    let qx = (x + o * dx).clamp(0, frame.width - 1);
+                    let qy = (y + o * dy).clamp(0, frame.height - 1);
+                    sum += src[frame.index(qx, qy)] * *w;
+                }
+                dst[frame.index(x, y)] = sum / norm;
+            }
+        }
+        dst
+    };
+
+    pass(&pass(image, 1, 0), 0, 1)
+}
+
+/// Soft glow around highlights. The highlights are convolved with a sum of gaussians of doubling
+/// width, approximating the long tailed scattering of real lenses, and added back scaled by `intensity`.
+#[derive(Copy, Clone, Debug)]
+pub struct Bloom {
+    pub threshold: f32,
+    pub intensity: f32,
+    /// Width of the narrowest gaussian, as a fraction of the full frame width.
+    pub radius: f32,
+    pub octaves: i32
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self { threshold: 1., intensity: 0.1, radius: 0.005, octaves: 4 }
+    }
+}
+
+#[allow(dead_code)]
+impl Bloom {
+    pub fn new(threshold: f32, intensity: f32) -> Self {
+        Self { threshold, intensity, ..Self::default() }
+    }
+
+    pub fn with_radius(mut self, radius: f32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn with_octaves(mut self, octaves: i32) -> Self {
+        self.octaves = octaves.max(1);
+        self
+    }
+}
+
+impl PostEffect for Bloom {
+    fn apply(&self, image: &mut [Color], frame: &Frame) {
+        let bright = highlights(image, self.threshold, frame.working_space);
+        let weight = self.intensity / self.octaves as f32;
+
+        let mut sigma = (self.radius * frame.full_width as f32).max(0.5);
+        for _ in 0..self.octaves {
+            for (c, b) in image.iter_mut().zip(blur(&bright, frame, sigma)) {
+                *c += b * weight;
+            }
+            sigma *= 2.;
+        }
+    }
+}
+
+/// Star shaped streaks from highlights, as diffraction on aperture blades produces. Every highlight
+/// is smeared along `streaks` directions with exponential falloff over `length` pixels.
+#[derive(Copy, Clone, Debug)]
+pub struct Glare {
+    pub threshold: f32,
+    pub intensity: f32,
+    pub streaks: i32,
+    pub length: f32,
+    /// Rotation of the first streak in radians.
+    pub angle: f32
+}
+
+impl Default for Glare {
+    fn default() -> Self {
+        Self { threshold: 2., intensity: 0.05, streaks: 6, length: 20., angle: 0.3 }
+    }
+}
+
+#[allow(dead_code)]
+impl Glare {
+    pub fn new(threshold: f32, intensity: f32, streaks: i32) -> Self {
+        Self { threshold, intensity, streaks: streaks.max(1), ..Self::default() }
+    }
+
+    pub fn with_length(mut self, length: f32) -> Self {
+        self.length = length;
+        self
+    }
+
+    pub fn with_angle(mut self, angle: f32) -> Self {
+        self.angle = angle;
+        self
+    }
+}
+
+impl PostEffect for Glare {
+    fn apply(&self, image: &mut [Color], frame: &Frame) {
+        let bright = highlights(image, self.threshold, frame.working_space);
+        let steps = (3. * self.length).ceil() as i32;
+
+        // total weight of one streak, so `intensity` is the fraction of a highlight spread out
+        let falloff: Vec<f32> = (1..=steps).map(|k| (-k as f32 / self.length).exp()).collect();
+        let scale = self.intensity / (falloff.iter().sum::<f32>() * self.streaks as f32);
+
+        let mut glare = vec![Color::zero(); image.len()];
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                let b = bright[frame.index(x, y)];
+                if b.x + b.y + b.z <= 0. {
+                    continue;
+                }
+
+                for s in 0..self.streaks {
+                    let theta = self.angle + std::f32::consts::TAU * s as f32 / self.streaks as f32;
+                    let (dy, dx) = theta.sin_cos();
+
+                    for (k, w) in falloff.iter().enumerate() {
+                        let qx = (x as f32 + 0.5 + dx * (k + 1) as f32).floor() as i32;
+                        let qy = (y as f32 + 0.5 + dy * (k + 1) as f32).floor() as i32;
+                        if qx < 0 || qy < 0 || qx >= frame.width || qy >= frame.height {
+                            break;
+                        }
+
+                        glare[frame.index(qx, qy)] += b * (w * scale);
+                    }
+                }
+            }
+        }
+
+        for (c, g) in image.iter_mut().zip(glare) {
+            *c += g;
+        }
+    }
+}
+
+/// Natural vignetting, the cos^4 falloff of light reaching the film at an angle. `strength` is the
+/// tangent of that angle at the frame corners.
+#[derive(Copy, Clone, Debug)]
+pub struct Vignette {
+    pub strength: f32
+}
+
+#[allow(dead_code)]
+impl Vignette {
+    pub fn new(strength: f32) -> Self {
+        Self { strength }
+    }
+}
+
+impl PostEffect for Vignette {
+    fn apply(&self, image: &mut [Color], frame: &Frame) {
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                let (dx, dy) = frame.offset_from_center(x, y);
+                let tan2 = (dx * dx + dy * dy) * self.strength * self.strength;
+                let cos2 = 1. / (1. + tan2);
+
+                image[frame.index(x, y)] *= cos2 * cos2;
+            }
+        }
+    }
+}
+
+/// Lateral chromatic aberration, red and blue magnified differently than green. At the frame
+/// corners red is shifted outwards and blue inwards by `shift` of the half diagonal.
+#[derive(Copy, Clone, Debug)]
+pub struct ChromaticAberration {
+    pub shift: f32
+}
+
+#[allow(dead_code)]
+impl ChromaticAberration {
+    pub fn new(shift: f32) -> Self {
+        Self { shift }
+    }
+}
+
+impl PostEffect for ChromaticAberration {
+    fn apply(&self, image: &mut [Color], frame: &Frame) {
+        let source = image.to_vec();
+        let cx = 0.5 * frame.full_width as f32 - frame.x0 as f32;
+        let cy = 0.5 * frame.full_height as f32 - frame.y0 as f32;
+
+        // a channel magnified by `m` shows at p what lies at center + (p - center) / m
+        let lookup = |x: f32, y: f32, m: f32| frame.sample(&source, cx + (x - cx) / m, cy + (y - cy) / m);
+
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let c = &mut image[frame.index(x, y)];
+
+                c.x = lookup(px, py, 1. + self.shift).x;
+                c.z = lookup(px, py, 1. - self.shift).z;
+            }
+        }
+    }
+}
+
+/// Photographic film grain from Perlin noise, strongest in the midtones. `amount` is the relative
+/// brightness variation and `size` the grain size in pixels. Different seeds give different grain.
+#[derive(Copy, Clone, Debug)]
+pub struct FilmGrain {
+    pub amount: f32,
+    pub size: f32,
+    pub seed: u32
+}
+
+#[allow(dead_code)]
+impl FilmGrain {
+    pub fn new(amount: f32, size: f32) -> Self {
+        Self { amount, size: size.max(0.1), seed: 0 }
+    }
+
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+impl PostEffect for FilmGrain {
+    fn apply(&self, image: &mut [Color], frame: &Frame) {
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                let p = Vector3::new((frame.x0 + x) as f32 / self.size, (frame.y0 + y) as f32 / self.size, self.seed as f32 + 0.5);
+                let n = 2. * Perlin::rand31(p) - 1.;
+
+                let c = &mut image[frame.index(x, y)];
+                let l = frame.working_space.luminance(*c).clamp(0., 1.);
+                let midtones = 4. * l * (1. - l);
+
+                *c *= (1. + self.amount * midtones * n).max(0.);
+            }
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::math::sampling;
+use crate::math::vector::{Color, Point2D, Vector3};
+use crate::simulation::bsdf::{Bsdf, BsdfSample, Bxdf, DielectricBxdf};
+use crate::simulation::checkpoint::SceneHasher;
+use crate::simulation::hittable::HitRecord;
+use crate::simulation::material::Material;
+use crate::simulation::microfacet::{self, TrowbridgeReitz};
+use crate::simulation::spectrum;
+use crate::simulation::texture::Texture;
+
+/// Scalar material input, either a constant or a texture lookup.
+pub enum Param {
+    Value(f32),
+    Map(Arc<dyn Texture>)
+}
+
+/// Color material input, either a constant or a texture lookup.
+pub enum ColorParam {
+    Value(Color),
+    Map(Arc<dyn Texture>)
+}
+
+/// Disney / principled BSDF (Burley 2012, 2015), parameters follow the usual DCC tool conventions.
+pub struct Principled {
+    base_color: ColorParam,
+    metallic: Param,
+    roughness: Param,
+    specular: Param,
+    specular_tint: Param,
+    sheen: Param,
+    sheen_tint: Param,
+    clearcoat: Param,
+    clearcoat_gloss: Param,
+    transmission: Param,
+    ior: f32,
+    emission: ColorParam,
+    emission_strength: Param
+}
+
+struct PrincipledBxdf {
+    base_color: Color,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    specular_tint: f32,
+    sheen: f32,
+    sheen_tint: f32,
+    clearcoat: f32,
+    transmission: f32,
+    distrib: TrowbridgeReitz,
+    clearcoat_alpha: f32,
+    dielectric: DielectricBxdf,
+    /// Lobe selection probabilities: diffuse, specular, clearcoat, transmission.
+    lobe_pdf: [f32; 4]
+}
+
+impl From<f32> for Param {
+    fn from(v: f32) -> Self {
+        Param::Value(v)
+    }
+}
+
+impl From<Arc<dyn Texture>> for Param {
+    fn from(t: Arc<dyn Texture>) -> Self {
+        Param::Map(t)
+    }
+}
+
+impl From<Color> for ColorParam {
+    fn from(c: Color) -> Self {
+        ColorParam::Value(c)
+    }
+}
+
+impl From<Arc<dyn Texture>> for ColorParam {
+    fn from(t: Arc<dyn Texture>) -> Self {
+        ColorParam::Map(t)
+    }
+}
+
+impl Param {
+    fn eval(&self, rec: &HitRecord) -> f32 {
+        match self {
+            Param::Value(v) => *v,
+            Param::Map(t) => t.scalar(rec.uv(), rec.p())
+        }
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        match self {
+            Param::Value(v) => h.write_f32(*v),
+            Param::Map(t) => t.fingerprint(h)
+        }
+    }
+}
+
+impl ColorParam {
+    fn eval(&self, rec: &HitRecord) -> Color {
+        match self {
+            ColorParam::Value(c) => *c,
+            ColorParam::Map(t) => t.value(rec.uv(), rec.p())
+        }
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        match self {
+            ColorParam::Value(c) => h.write_vector(*c),
+            ColorParam::Map(t) => t.fingerprint(h)
+        }
+    }
+}
+
+fn luminance(c: Color) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+fn schlick_weight(cos: f32) -> f32 {
+    let m = (1. - cos).clamp(0., 1.);
+    let m2 = m * m;
+    m2 * m2 * m
+}
+
+fn schlick(f0: Color, cos: f32) -> Color {
+    Color::lerp(f0, Color::new(1., 1., 1.), schlick_weight(cos))
+}
+
+/// GTR1 (Berry) distribution used by the clearcoat lobe.
+fn gtr1(cos_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    (a2 - 1.) / (PI * a2.ln() * (1. + (a2 - 1.) * cos_h * cos_h))
+}
+
+fn smith_g1_ggx(cos: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let c2 = cos * cos;
+    2. * cos.abs() / (cos.abs() + (a2 + c2 - a2 * c2).sqrt())
+}
+
+#[allow(dead_code)]
+impl Principled {
+    pub fn new<C: Into<ColorParam>>(base_color: C) -> Self {
+        Self {
+            base_color: base_color.into(),
+            metallic: Param::Value(0.),
+            roughness: Param::Value(0.5),
+            specular: Param::Value(0.5),
+            specular_tint: Param::Value(0.),
+            sheen: Param::Value(0.),
+            sheen_tint: Param::Value(0.5),
+            clearcoat: Param::Value(0.),
+            clearcoat_gloss: Param::Value(1.),
+            transmission: Param::Value(0.),
+            ior: 1.45,
+            emission: ColorParam::Value(Color::zero()),
+            emission_strength: Param::Value(1.)
+        }
+    }
+
+    pub fn with_metallic<P: Into<Param>>(mut self, v: P) -> Self {
+        self.metallic = v.into();
+        self
+    }
+
+    pub fn with_roughness<P: Into<Param>>(mut self, v: P) -> Self {
+        self.roughness = v.into();
+        self
+    }
+
+    /// Dielectric reflectance at normal incidence remapped to [0, 1], 0.5 is 4%.
+    pub fn with_specular<P: Into<Param>>(mut self, v: P) -> Self {
+        self.specular = v.into();
+        self
+    }
+
+    pub fn with_specular_tint<P: Into<Param>>(mut self, v: P) -> Self {
+        self.specular_tint = v.into();
+        self
+    }
+
+    pub fn with_sheen<P: Into<Param>>(mut self, v: P) -> Self {
+        self.sheen = v.into();
+        self
+    }
+
+    pub fn with_sheen_tint<P: Into<Param>>(mut self, v: P) -> Self {
+        self.sheen_tint = v.into();
+        self
+    }
+
+    pub fn with_clearcoat<P: Into<Param>>(mut self, v: P) -> Self {
+        self.clearcoat = v.into();
+        self
+    }
+
+    pub fn with_clearcoat_gloss<P: Into<Param>>(mut self, v: P) -> Self {
+        self.clearcoat_gloss = v.into();
+        self
+    }
+
+    pub fn with_transmission<P: Into<Param>>(mut self, v: P) -> Self {
+        self.transmission = v.into();
+        self
+    }
+
+    pub fn with_ior(mut self, ior: f32) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    pub fn with_emission<C: Into<ColorParam>>(mut self, c: C) -> Self {
+        self.emission = c.into();
+        self
+    }
+
+    pub fn with_emission_strength<P: Into<Param>>(mut self, v: P) -> Self {
+        self.emission_strength = v.into();
+        self
+    }
+}
+
+impl Material for Principled {
+    fn bsdf(&self, rec: &HitRecord) -> Bsdf {
+        let base_color = spectrum::at_wavelength(self.base_color.eval(rec), rec.wavelength());
+        let metallic = self.metallic.eval(rec).clamp(0., 1.);
+        let roughness = self.roughness.eval(rec).clamp(0., 1.);
+        let transmission = self.transmission.eval(rec).clamp(0., 1.);
+        let clearcoat = self.clearcoat.eval(rec).max(0.);
+        let clearcoat_gloss = self.clearcoat_gloss.eval(rec).clamp(0., 1.);
+
+        // glass may turn into a perfect mirror / refractor, the reflection lobes are evaluated
+        // through the microfacet terms directly and need a minimum roughness
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness);
+        let distrib = TrowbridgeReitz::new(alpha.max(1e-3), alpha.max(1e-3));
+
+        let eta = if rec.front_face() { self.ior } else { 1. / self.ior };
+        let dielectric = DielectricBxdf::new(TrowbridgeReitz::new(alpha, alpha), eta);
+
+        let w_diffuse = (1. - metallic) * (1. - transmission);
+        let w_transmission = (1. - metallic) * transmission;
+        let w_specular = 1. - w_transmission;
+        let w_clearcoat = 0.25 * clearcoat;
+
+        let mut lobe_pdf = [
+            w_diffuse * luminance(base_color).max(0.01),
+            w_specular,
+            w_clearcoat,
+            w_transmission
+        ];
+        let sum: f32 = lobe_pdf.iter().sum();
+        for p in lobe_pdf.iter_mut() {
+            *p /= sum;
+        }
+
+        let bxdf = PrincipledBxdf {
+            base_color,
+            metallic,
+            roughness,
+            specular: self.specular.eval(rec).max(0.),
+            specular_tint: self.specular_tint.eval(rec).clamp(0., 1.),
+            sheen: self.sheen.eval(rec).max(0.),
+            sheen_tint: self.sheen_tint.eval(rec).clamp(0., 1.),
+            clearcoat,
+            transmission,
+            distrib,
+            clearcoat_alpha: 0.1 - 0.099 * clearcoat_gloss,
+            dielectric,
+            lobe_pdf
+        };
+
+        Bsdf::new(rec.shading_frame(), Box::new(bxdf))
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        if !rec.front_face() {
+            return Color::zero();
+        }
+
+        spectrum::at_wavelength(self.emission.eval(rec), rec.wavelength()) * self.emission_strength.eval(rec)
+    }
+
+    fn is_emissive(&self) -> bool {
+        let black = matches!(self.emission, ColorParam::Value(c) if c.x <= 0. && c.y <= 0. && c.z <= 0.);
+        let off = matches!(self.emission_strength, Param::Value(v) if v <= 0.);
+        !black && !off
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base_color.eval(rec)
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("Principled");
+        self.base_color.fingerprint(h);
+        for param in [
+            &self.metallic, &self.roughness, &self.specular, &self.specular_tint, &self.sheen, &self.sheen_tint,
+            &self.clearcoat, &self.clearcoat_gloss, &self.transmission, &self.emission_strength
+        ] {
+            param.fingerprint(h);
+        }
+        h.write_f32(self.ior);
+        self.emission.fingerprint(h);
+    }
+}
+
+impl PrincipledBxdf {
+    /// Weight of the glass lobe, only light refracted through it picks up the base color.
+    fn transmission_weight(&self, wo: Vector3, wi: Vector3) -> Color {
+        let w = (1. - self.metallic) * self.transmission;
+        if wo.z * wi.z < 0. { self.base_color * w } else { Color::new(w, w, w) }
+    }
+
+    fn tint(&self) -> Color {
+        let lum = luminance(self.base_color);
+        if lum > 0. { self.base_color / lum } else { Color::new(1., 1., 1.) }
+    }
+
+    fn specular_f0(&self) -> Color {
+        let dielectric = Color::lerp(Color::new(1., 1., 1.), self.tint(), self.specular_tint) * (0.08 * self.specular);
+        Color::lerp(dielectric, self.base_color, self.metallic)
+    }
+
+    fn diffuse_f(&self, wo: Vector3, wi: Vector3, cos_d: f32) -> Color {
+        let fo = schlick_weight(wo.z.abs());
+        let fi = schlick_weight(wi.z.abs());
+
+        // Burley retro-reflection
+        let fd90 = 0.5 + 2. * self.roughness * cos_d * cos_d;
+        let fd = (1. + (fd90 - 1.) * fo) * (1. + (fd90 - 1.) * fi);
+
+        let sheen = Color::lerp(Color::new(1., 1., 1.), self.tint(), self.sheen_tint) * (self.sheen * schlick_weight(cos_d));
+
+        self.base_color * (fd / PI) + sheen
+    }
+
+    fn specular_f(&self, wo: Vector3, wi: Vector3, wm: Vector3) -> Color {
+        let f = schlick(self.specular_f0(), wi.dot(wm).abs());
+        f * (self.distrib.d(wm) * self.distrib.g(wo, wi) / (4. * wo.z.abs() * wi.z.abs()))
+    }
+
+    fn clearcoat_f(&self, wo: Vector3, wi: Vector3, wm: Vector3) -> f32 {
+        let d = gtr1(wm.z.abs(), self.clearcoat_alpha);
+        let f = 0.04 + 0.96 * schlick_weight(wi.dot(wm).abs());
+        let g = smith_g1_ggx(wo.z, 0.25) * smith_g1_ggx(wi.z, 0.25);
+
+        self.clearcoat * 0.25 * d * f * g / (4. * wo.z.abs() * wi.z.abs())
+    }
+
+    fn clearcoat_pdf(&self, wo: Vector3, wm: Vector3) -> f32 {
+        gtr1(wm.z.abs(), self.clearcoat_alpha) * wm.z.abs() / (4. * wo.dot(wm).abs())
+    }
+}
+
+impl Bxdf for PrincipledBxdf {
+    fn f(&self, wo: Vector3, wi: Vector3) -> Color {
+        let mut f = self.dielectric.f(wo, wi) * self.transmission_weight(wo, wi);
+
+        if wo.z * wi.z <= 0. {
+            return f;
+        }
+
+        let wm = wo + wi;
+        if wm.magnitude_squared() == 0. {
+            return f;
+        }
+        let wm = wm.normalized();
+        let wm = if wm.z < 0. { -wm } else { wm };
+        let cos_d = wi.dot(wm).abs();
+
+        f += self.diffuse_f(wo, wi, cos_d) * ((1. - self.metallic) * (1. - self.transmission));
+        f += self.specular_f(wo, wi, wm) * (1. - (1. - self.metallic) * self.transmission);
+
+        let cc = self.clearcoat_f(wo, wi, wm);
+        f + Color::new(cc, cc, cc)
+    }
+
+    fn sample_f(&self, wo: Vector3, uc: f32, u: Point2D) -> Option<BsdfSample> {
+        let [p_diffuse, p_specular, p_clearcoat, p_transmission] = self.lobe_pdf;
+
+        // only the transmission lobe refracts, the others keep eta at 1
+        let (wi, eta) = if uc < p_diffuse {
+            let mut wi = sampling::cosine_hemisphere(u);
+            if wo.z < 0. {
+                wi.z = -wi.z;
+            }
+            (wi, 1.)
+        } else if uc < p_diffuse + p_specular {
+            let wm = self.distrib.sample_wm(wo, u);
+            (microfacet::reflect(wo, wm), 1.)
+        } else if uc < p_diffuse + p_specular + p_clearcoat {
+            let a2 = self.clearcoat_alpha * self.clearcoat_alpha;
+            let cos_h = ((1. - a2.powf(1. - u.x)) / (1. - a2)).max(0.).sqrt();
+            let sin_h = (1. - cos_h * cos_h).max(0.).sqrt();
+            let phi = 2. * PI * u.y;
+
+            let wm = Vector3::new(sin_h * phi.cos(), sin_h * phi.sin(), cos_h);
+            let wm = if wo.z < 0. { -wm } else { wm };
+            (microfacet::reflect(wo, wm), 1.)
+        } else {
+            if p_transmission <= 0. {
+                return None;
+            }
+
+            let start = p_diffuse + p_specular + p_clearcoat;
+            let uc = ((uc - start) / p_transmission).min(1. - f32::EPSILON);
+            let s = self.dielectric.sample_f(wo, uc, u)?;
+
+            // a delta lobe cannot be combined with the others
+            if s.specular {
+                let weight = self.transmission_weight(wo, s.wi);
+                return Some(BsdfSample { wi: s.wi, f: s.f * weight, pdf: s.pdf * p_transmission, specular: true, eta: s.eta });
+            }
+            (s.wi, s.eta)
+        };
+
+        let pdf = self.pdf(wo, wi);
+        if pdf <= 0. {
+            return None;
+        }
+
+        Some(BsdfSample { wi, f: self.f(wo, wi), pdf, specular: false, eta })
+    }
+
+    fn pdf(&self, wo: Vector3, wi: Vector3) -> f32 {
+        let [p_diffuse, p_specular, p_clearcoat, p_transmission] = self.lobe_pdf;
+
+        let mut pdf = p_transmission * self.dielectric.pdf(wo, wi);
+
+        if wo.z * wi.z <= 0. {
+            return pdf;
+        }
+
+        let wm = wo + wi;
+        if wm.magnitude_squared() == 0. {
+            return pdf;
+        }
+        let wm = wm.normalized();
+        let wm = if wm.z < 0. { -wm } else { wm };
+
+        pdf += p_diffuse * sampling::cosine_hemisphere_pdf(wi.z.abs());
+        pdf += p_specular * self.distrib.pdf(wo, wm) / (4. * wo.dot(wm).abs());
+        if p_clearcoat > 0. {
+            pdf += p_clearcoat * self.clearcoat_pdf(wo, wm);
+        }
+
+        pdf
+    }
+
+    /// Glossy without a diffuse part, e.g. smooth glass or polished metal. Specular reflection and
+    /// transmission share the same roughness.
+    fn is_glossy(&self) -> bool {
+        let diffuse = (1. - self.metallic) * (1. - self.transmission);
+        let clearcoat = TrowbridgeReitz::new(self.clearcoat_alpha, self.clearcoat_alpha);
+
+        diffuse <= 0. && self.distrib.is_glossy() && (self.clearcoat <= 0. || clearcoat.is_glossy())
+    }
+
+    fn eta(&self) -> f32 {
+        self.dielectric.eta()
+    }
+}
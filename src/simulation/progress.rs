@@ -0,0 +1,70 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Snapshot of a running `Engine::simulate`, reported after every finished tile.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug)]
+pub struct Progress {
+    /// Current pass, starting at 1. Without progressive rendering there is a single pass.
+    pub pass: i32,
+    /// Upper bound on the number of passes, fewer are needed when pixels converge early.
+    /// 0 when passes go on until the time limit.
+    pub passes: i32,
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    pub samples: u64,
+    pub rays: u64,
+    /// Samples limited by sample clamping.
+    pub clamped: u64,
+    pub elapsed: Duration,
+    pub samples_per_sec: f64,
+    pub rays_per_sec: f64,
+    pub eta: Option<Duration>
+}
+
+#[allow(dead_code)]
+impl Progress {
+    /// Fraction of the whole render done, in [0, 1]. Renders until the time limit count elapsed time.
+    pub fn fraction(&self) -> f64 {
+        if self.passes == 0 {
+            let elapsed = self.elapsed.as_secs_f64();
+            return self.eta.map_or(0., |eta| elapsed / (elapsed + eta.as_secs_f64()).max(1e-6));
+        }
+        if self.tiles_total == 0 || self.passes < 0 {
+            return 1.;
+        }
+
+        let pass = (self.pass - 1) as f64 + self.tiles_done as f64 / self.tiles_total as f64;
+        (pass / self.passes as f64).clamp(0., 1.)
+    }
+}
+
+pub type ProgressCallback = Box<dyn Fn(&Progress) + Send + Sync>;
+
+/// Shared flag to stop a render early. Workers finish the pixel they are on and return,
+/// everything sampled so far stays on the film.
+#[derive(Clone, Default, Debug)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>
+}
+
+#[allow(dead_code)]
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Clears the flag so the engine can render again.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+}
@@ -1,19 +1,34 @@
 use crate::math::vector::Vector3;
 use crate::math::vector::Point3D;
+use crate::simulation::spectrum::Wavelength;
 
 pub struct Ray {
     origin: Point3D,
-    direction: Vector3
+    direction: Vector3,
+    wavelength: Option<Wavelength>
 }
 
+#[allow(dead_code)]
 impl Ray {
     pub fn new(orig: &Point3D, dir: &Vector3) -> Self {
         Self {
             origin: *orig,
-            direction: *dir
+            direction: *dir,
+            wavelength: None
         }
     }
 
+    /// Tags the ray with the wavelength its path carries in spectral mode.
+    pub fn with_wavelength(mut self, wavelength: Option<Wavelength>) -> Self {
+        self.wavelength = wavelength;
+        self
+    }
+
+    /// Continues the path from a new vertex, keeping the wavelength.
+    pub fn spawn(&self, orig: &Point3D, dir: &Vector3) -> Self {
+        Self::new(orig, dir).with_wavelength(self.wavelength)
+    }
+
     pub fn origin(&self) -> Point3D {
         self.origin
     }
@@ -22,6 +37,11 @@ impl Ray {
         self.direction
     }
 
+    /// None when rendering in RGB.
+    pub fn wavelength(&self) -> Option<Wavelength> {
+        self.wavelength
+    }
+
     pub fn at(&self, t: f32) -> Vector3 {
         self.origin + self.direction * t
     }
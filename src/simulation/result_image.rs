@@ -2,6 +2,7 @@ use crate::math::array::Array;
 
 pub type RGB256 = Array<u8, 3>;
 
+#[allow(dead_code)]
 pub struct ResultImage {
     width: i32,
     height: i32,
@@ -9,6 +10,7 @@ pub struct ResultImage {
     pub pixels: Vec<RGB256>
 }
 
+#[allow(dead_code)]
 impl ResultImage {
     pub fn new(w: i32, h: i32) -> ResultImage {
         Self {
@@ -0,0 +1,63 @@
+use crate::math::vector::Point2D;
+
+/// Source of uniform random numbers in [0, 1) for an integrator.
+pub trait Sampler {
+    fn next_1d(&mut self) -> f32;
+
+    fn next_2d(&mut self) -> Point2D {
+        let x = self.next_1d();
+        let y = self.next_1d();
+        Point2D::new(x, y)
+    }
+}
+
+/// Independent uniform samples from a PCG32 stream.
+#[derive(Copy, Clone)]
+pub struct RandomSampler {
+    state: u64,
+    inc: u64
+}
+
+#[allow(dead_code)]
+impl RandomSampler {
+    const MULTIPLIER: u64 = 0x5851f42d4c957f2d;
+
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut s = Self { state: 0, inc: (stream << 1) | 1 };
+        s.next_u32();
+        s.state = s.state.wrapping_add(seed);
+        s.next_u32();
+        s
+    }
+
+    /// Decorrelated stream for one pixel of the image.
+    pub fn for_pixel(x: i32, y: i32, seed: u64) -> Self {
+        let pixel = ((y as u32 as u64) << 32) | x as u32 as u64;
+        Self::new(seed, pixel)
+    }
+
+    /// Restores a generator from the values returned by `state`.
+    pub fn from_state(state: u64, inc: u64) -> Self {
+        Self { state, inc }
+    }
+
+    pub fn state(&self) -> (u64, u64) {
+        (self.state, self.inc)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER).wrapping_add(self.inc);
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn next_1d(&mut self) -> f32 {
+        // 24 bits keep the result strictly below 1
+        (self.next_u32() >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+    }
+}
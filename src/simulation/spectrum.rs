@@ -0,0 +1,231 @@
+use std::hash::Hasher;
+use std::sync::OnceLock;
+
+use crate::math::color::ColorSpace;
+use crate::math::vector::{Color, Vector3};
+use crate::simulation::checkpoint::SceneHasher;
+
+pub const LAMBDA_MIN: f32 = 360.;
+pub const LAMBDA_MAX: f32 = 830.;
+
+/// Wavelength-dependent index of refraction, wavelengths in nanometers.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum Ior {
+    Constant(f32),
+    /// n = a + b / lambda^2, lambda in micrometers.
+    Cauchy { a: f32, b: f32 },
+    /// n^2 = 1 + sum(b_i lambda^2 / (lambda^2 - c_i)), lambda in micrometers.
+    Sellmeier { b: [f32; 3], c: [f32; 3] }
+}
+
+#[allow(dead_code)]
+impl Ior {
+    /// Wavelength used when rendering in RGB.
+    pub const REFERENCE_LAMBDA: f32 = 550.;
+
+    pub fn fingerprint(&self, h: &mut SceneHasher) {
+        match *self {
+            Ior::Constant(eta) => {
+                h.write_u8(0);
+                h.write_f32(eta);
+            }
+            Ior::Cauchy { a, b } => {
+                h.write_u8(1);
+                h.write_f32(a);
+                h.write_f32(b);
+            }
+            Ior::Sellmeier { b, c } => {
+                h.write_u8(2);
+                for v in b.iter().chain(c.iter()) {
+                    h.write_f32(*v);
+                }
+            }
+        }
+    }
+
+    pub fn bk7() -> Self {
+        Ior::Sellmeier {
+            b: [1.039_612, 0.231_792_34, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65]
+        }
+    }
+
+    pub fn fused_silica() -> Self {
+        Ior::Sellmeier {
+            b: [0.696_166_3, 0.407_942_6, 0.897_479_4],
+            c: [0.004_679_148, 0.013_512_063, 97.934]
+        }
+    }
+
+    /// Dense flint glass, strongly dispersive.
+    pub fn sf11() -> Self {
+        Ior::Sellmeier {
+            b: [1.737_596_9, 0.313_747_35, 1.898_781],
+            c: [0.013_188_707, 0.062_306_814, 155.236_3]
+        }
+    }
+
+    pub fn eval(&self, lambda: f32) -> f32 {
+        let l = lambda * 1e-3;
+        let l2 = l * l;
+
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let n2 = 1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>();
+                n2.max(1.).sqrt()
+            }
+        }
+    }
+
+    pub fn at(&self, lambda: Option<f32>) -> f32 {
+        self.eval(lambda.unwrap_or(Self::REFERENCE_LAMBDA))
+    }
+}
+
+fn gaussian(x: f32, mu: f32, sigma1: f32, sigma2: f32) -> f32 {
+    let t = (x - mu) / if x < mu { sigma1 } else { sigma2 };
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 2 degree color matching functions, multi-lobe fit of Wyman et al. 2013.
+pub fn cie_xyz(lambda: f32) -> Vector3 {
+    Vector3::new(
+        1.056 * gaussian(lambda, 599.8, 37.9, 31.0) + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2),
+        0.821 * gaussian(lambda, 568.8, 46.9, 40.5) + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1),
+        1.217 * gaussian(lambda, 437.0, 11.8, 36.0) + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8)
+    )
+}
+
+/// Wavelength (nm) a path carries in spectral mode, with the working space the scene colors it
+/// meets are given in.
+#[derive(Copy, Clone, Debug)]
+pub struct Wavelength {
+    pub lambda: f32,
+    pub working_space: ColorSpace
+}
+
+/// Samples a wavelength roughly proportional to the eye's sensitivity (pbrt-v4), returns (lambda, pdf).
+pub fn sample_visible(u: f32) -> (f32, f32) {
+    let lambda = 538. - 138.888_89 * (0.856_910_6 - 1.827_502 * u).atanh();
+    (lambda, visible_pdf(lambda))
+}
+
+pub fn visible_pdf(lambda: f32) -> f32 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.;
+    }
+
+    let c = (0.0072 * (lambda - 538.)).cosh();
+    0.003_939_804 / (c * c)
+}
+
+struct Calibration {
+    y_integral: f32,
+    /// Per component scale taking the equal energy white to D65.
+    white_balance: Vector3
+}
+
+const D65_WHITE: Vector3 = Vector3 { x: 0.950_47, y: 1.0, z: 1.088_83 };
+
+/// Normalization so that a constant unit spectrum comes out as the D65 white with Y = 1.
+fn calibration() -> &'static Calibration {
+    static CALIBRATION: OnceLock<Calibration> = OnceLock::new();
+
+    CALIBRATION.get_or_init(|| {
+        let mut sum = Vector3::zero();
+        let steps = 4700;
+        let dl = (LAMBDA_MAX - LAMBDA_MIN) / steps as f32;
+
+        for i in 0..steps {
+            sum += cie_xyz(LAMBDA_MIN + (i as f32 + 0.5) * dl) * dl;
+        }
+
+        Calibration {
+            y_integral: sum.y,
+            white_balance: D65_WHITE / (sum / sum.y)
+        }
+    })
+}
+
+/// Converts one spectral radiance sample taken with probability `pdf` to a CIE XYZ estimate.
+pub fn sample_to_xyz(lambda: f32, pdf: f32, radiance: f32) -> Vector3 {
+    if pdf <= 0. {
+        return Vector3::zero();
+    }
+
+    let cal = calibration();
+
+    cie_xyz(lambda) * (radiance / (pdf * cal.y_integral)) * cal.white_balance
+}
+
+// Smits 1999, "An RGB to Spectrum Conversion for Reflectances", 10 bins over 380..720 nm.
+const SMITS_MIN: f32 = 380.;
+const SMITS_MAX: f32 = 720.;
+
+const SMITS_WHITE: [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+/// Value at `lambda` of a smooth spectrum whose linear sRGB color is `c`.
+pub fn upsample(c: Color, lambda: f32) -> f32 {
+    // negative components come from out-of-gamut conversions and have no reflectance meaning
+    let c = Color::new(c.x.max(0.), c.y.max(0.), c.z.max(0.));
+    let t = ((lambda - SMITS_MIN) / (SMITS_MAX - SMITS_MIN) * 10.).clamp(0., 9.999);
+    let bin = t as usize;
+    let s = |table: &[f32; 10]| table[bin];
+
+    let (r, g, b) = (c.x, c.y, c.z);
+
+    if r <= g && r <= b {
+        r * s(&SMITS_WHITE) + if g <= b {
+            (g - r) * s(&SMITS_CYAN) + (b - g) * s(&SMITS_BLUE)
+        } else {
+            (b - r) * s(&SMITS_CYAN) + (g - b) * s(&SMITS_GREEN)
+        }
+    } else if g <= r && g <= b {
+        g * s(&SMITS_WHITE) + if r <= b {
+            (r - g) * s(&SMITS_MAGENTA) + (b - r) * s(&SMITS_BLUE)
+        } else {
+            (b - g) * s(&SMITS_MAGENTA) + (r - b) * s(&SMITS_RED)
+        }
+    } else {
+        b * s(&SMITS_WHITE) + if r <= g {
+            (r - b) * s(&SMITS_YELLOW) + (g - r) * s(&SMITS_GREEN)
+        } else {
+            (g - b) * s(&SMITS_YELLOW) + (r - g) * s(&SMITS_RED)
+        }
+    }
+}
+
+/// Piecewise linear curve through per-channel values placed at 650 / 550 / 450 nm,
+/// for measured data such as complex IORs that are stored as rgb triples.
+pub fn rgb_curve(c: Color, lambda: f32) -> f32 {
+    if lambda >= 650. {
+        c.x
+    } else if lambda >= 550. {
+        c.y + (c.x - c.y) * (lambda - 550.) / 100.
+    } else if lambda >= 450. {
+        c.z + (c.y - c.z) * (lambda - 450.) / 100.
+    } else {
+        c.z
+    }
+}
+
+/// Working space color as seen by a path: unchanged in RGB mode, a grey value in spectral mode.
+pub fn at_wavelength(c: Color, wavelength: Option<Wavelength>) -> Color {
+    match wavelength {
+        Some(w) => {
+            let v = upsample(ColorSpace::convert(c, w.working_space, ColorSpace::LinearSrgb), w.lambda);
+            Color::new(v, v, v)
+        }
+        None => c
+    }
+}
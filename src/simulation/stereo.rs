@@ -0,0 +1,307 @@
+use std::f32::consts::{PI, TAU};
+use std::hash::Hasher;
+
+use crate::math::vector::{Point2D, Point3D, Vector3};
+use crate::simulation::camera::{CameraModel, ImportanceSample, View};
+use crate::simulation::checkpoint::SceneHasher;
+use crate::simulation::ray::Ray;
+
+/// How several views share one film, first view left or on top.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Layout {
+    SideBySide,
+    OverUnder
+}
+
+impl Layout {
+    /// Index of the view covering film position (u, v) out of `count`, and the position within that view.
+    pub fn split(self, u: f32, v: f32, count: usize) -> (usize, f32, f32) {
+        let (t, rest) = match self {
+            Layout::SideBySide => (u, v),
+            Layout::OverUnder => (v, u)
+        };
+
+        let scaled = t * count as f32;
+        let index = (scaled as usize).min(count - 1);
+        let local = scaled - index as f32;
+
+        match self {
+            Layout::SideBySide => (index, local, rest),
+            Layout::OverUnder => (index, rest, local)
+        }
+    }
+
+    /// Film position of (u, v) within view `index` out of `count`, the inverse of `split`.
+    pub fn merge(self, index: usize, u: f32, v: f32, count: usize) -> (f32, f32) {
+        match self {
+            Layout::SideBySide => ((index as f32 + u) / count as f32, v),
+            Layout::OverUnder => (u, (index as f32 + v) / count as f32)
+        }
+    }
+
+    /// Film aspect ratio holding `count` views of `aspect_ratio` each.
+    pub fn aspect_ratio(self, aspect_ratio: f32, count: usize) -> f32 {
+        match self {
+            Layout::SideBySide => aspect_ratio * count as f32,
+            Layout::OverUnder => aspect_ratio / count as f32
+        }
+    }
+
+    fn fingerprint(self, h: &mut SceneHasher) {
+        h.write_u8(self as u8);
+    }
+}
+
+/// Eye positions along the view's right axis, left first.
+fn eye_offsets(ipd: f32) -> [f32; 2] {
+    [-0.5 * ipd, 0.5 * ipd]
+}
+
+/// Stereo pair of any camera model. Both eyes use the projection of `camera`, moved apart by the
+/// interocular distance `ipd` along its right axis. Rays of a film position meet at `convergence`
+/// distance in front of the camera, so objects there have zero parallax. This is the off-axis
+/// frustum for perspective cameras, infinite convergence keeps the eyes parallel. Light paths connect
+/// to either eye, exactly for pinhole and thin lens cameras with a lens narrower than `ipd`.
+pub struct Stereo<C: CameraModel> {
+    camera: C,
+    ipd: f32,
+    convergence: f32,
+    layout: Layout
+}
+
+#[allow(dead_code)]
+impl<C: CameraModel> Stereo<C> {
+    pub fn new(camera: C, ipd: f32, convergence: f32) -> Self {
+        Self { camera, ipd, convergence, layout: Layout::SideBySide }
+    }
+
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Point the eye ray from `origin` through `p` crosses the convergence plane at, None behind the eye.
+    fn convergence_point(&self, origin: Point3D, p: Point3D) -> Option<Point3D> {
+        let q = p - origin;
+        let depth = q.dot(self.camera.view().forward);
+        if depth <= 1e-6 {
+            return None;
+        }
+
+        Some(origin + q * (self.convergence / depth))
+    }
+
+    /// Density over directions of the converged eye ray from `origin` to `target`, relative to that of
+    /// the camera's own ray from `lens` to `target`. Both reach the plane at the same depth, so solid
+    /// angles there scale with cos^3.
+    fn convergence_ratio(&self, lens: Point3D, origin: Point3D, target: Point3D) -> f32 {
+        let forward = self.camera.view().forward;
+        let cos_camera = (target - lens).normalized().dot(forward);
+        let cos_eye = (target - origin).normalized().dot(forward);
+        (cos_camera / cos_eye).powi(3)
+    }
+}
+
+impl<C: CameraModel> CameraModel for Stereo<C> {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        self.sample_ray(u, v, Point2D::new(0.5, 0.5)).map(|(ray, _)| ray)
+    }
+
+    fn sample_ray(&self, u: f32, v: f32, lens: Point2D) -> Option<(Ray, f32)> {
+        let (eye, u, v) = self.layout.split(u, v, 2);
+        let (ray, weight) = self.camera.sample_ray(u, v, lens)?;
+        let view = self.camera.view();
+
+        let offset = view.right * eye_offsets(self.ipd)[eye];
+        let origin = ray.origin() + offset;
+
+        // the point both eyes see through this film position, on the plane at convergence distance
+        let depth = ray.direction().dot(view.forward);
+        if !self.convergence.is_finite() || depth <= 1e-6 {
+            return Some((Ray::new(&origin, &ray.direction()), weight));
+        }
+
+        let target = ray.origin() + ray.direction() * (self.convergence / depth);
+        Some((Ray::new(&origin, &(target - origin)), weight))
+    }
+
+    fn sample_importance(&self, p: Point3D, u: Point2D) -> Option<ImportanceSample> {
+        // each eye covers half of the film and is chosen half of the time, the weight stays as is
+        let (eye, x) = if u.x < 0.5 { (0, u.x * 2.) } else { (1, u.x * 2. - 1.) };
+        let u = Point2D::new(x, u.y);
+        let view = self.camera.view();
+        let offset = view.right * eye_offsets(self.ipd)[eye];
+
+        let sample = if !self.convergence.is_finite() {
+            let s = self.camera.sample_importance(p - offset, u)?;
+            ImportanceSample { origin: s.origin + offset, ..s }
+        } else {
+            // the lens point only depends on u, take it from a point every camera sees
+            let lens = self.camera.sample_importance(view.origin + view.forward * self.convergence, u)?.origin;
+            let origin = lens + offset;
+            let target = self.convergence_point(origin, p)?;
+
+            let s = self.camera.sample_importance(target, u)?;
+            let ratio = self.convergence_ratio(lens, origin, target);
+            let weight = s.weight * ratio * (target - lens).magnitude_squared() / (p - origin).magnitude_squared();
+            ImportanceSample { origin, film: s.film, weight }
+        };
+
+        let (fu, fv) = self.layout.merge(eye, sample.film.x, sample.film.y, 2);
+        Some(ImportanceSample { film: Point2D::new(fu, fv), ..sample })
+    }
+
+    fn pdf_direction(&self, ray: &Ray) -> f32 {
+        let view = self.camera.view();
+        let eye = if (ray.origin() - view.origin).dot(view.right) < 0. { 0 } else { 1 };
+        let offset = view.right * eye_offsets(self.ipd)[eye];
+        let lens = ray.origin() - offset;
+
+        // the eye is chosen half of the time
+        if !self.convergence.is_finite() {
+            return 0.5 * self.camera.pdf_direction(&Ray::new(&lens, &ray.direction()));
+        }
+
+        let Some(target) = self.convergence_point(ray.origin(), ray.origin() + ray.direction()) else {
+            return 0.;
+        };
+        let pdf = self.camera.pdf_direction(&Ray::new(&lens, &(target - lens)));
+        0.5 * pdf * self.convergence_ratio(lens, ray.origin(), target)
+    }
+
+    fn view(&self) -> View {
+        self.camera.view()
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("Stereo");
+        self.camera.fingerprint(h);
+        h.write_f32(self.ipd);
+        h.write_f32(self.convergence);
+        self.layout.fingerprint(h);
+    }
+}
+
+/// Omni-directional stereo panorama, an equirectangular image per eye. Every longitude is seen from
+/// eye positions on a circle of diameter `ipd`, tangent to the ray, so the stereo effect holds all
+/// around. The eyes move together towards the poles, which would otherwise show swapped parallax.
+/// Rays meet at `convergence` distance, infinite keeps them parallel.
+pub struct OmniStereo {
+    view: View,
+    ipd: f32,
+    convergence: f32,
+    layout: Layout
+}
+
+#[allow(dead_code)]
+impl OmniStereo {
+    pub fn new(ipd: f32) -> Self {
+        Self { view: View::default(), ipd, convergence: f32::INFINITY, layout: Layout::OverUnder }
+    }
+
+    pub fn with_view(mut self, view: View) -> Self {
+        self.view = view;
+        self
+    }
+
+    pub fn with_convergence(mut self, convergence: f32) -> Self {
+        self.convergence = convergence;
+        self
+    }
+
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+}
+
+impl CameraModel for OmniStereo {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        let (eye, u, v) = self.layout.split(u, v, 2);
+
+        let longitude = (u - 0.5) * TAU;
+        let latitude = (0.5 - v) * PI;
+
+        let dir = self.view.direction(latitude.cos() * longitude.sin(), latitude.sin(), latitude.cos() * longitude.cos());
+
+        // to the right of the horizontal viewing direction, scaled down towards the poles
+        let side = self.view.direction(longitude.cos(), 0., -longitude.sin());
+        let origin = self.view.origin + side * (eye_offsets(self.ipd)[eye] * latitude.cos());
+
+        if !self.convergence.is_finite() {
+            return Some(Ray::new(&origin, &dir));
+        }
+
+        let target = self.view.origin + dir * self.convergence;
+        Some(Ray::new(&origin, &(target - origin)))
+    }
+
+    fn view(&self) -> View {
+        self.view
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("OmniStereo");
+        self.view.fingerprint(h);
+        h.write_f32(self.ipd);
+        h.write_f32(self.convergence);
+        self.layout.fingerprint(h);
+    }
+}
+
+/// Six 90 degree square faces around the camera: right, left, up, down, front and back, in a row
+/// or a column. Side faces keep the view's up, the up face has the back at its top and the down
+/// face the front.
+pub struct CubeMap {
+    view: View,
+    layout: Layout
+}
+
+#[allow(dead_code)]
+impl CubeMap {
+    pub fn new(layout: Layout) -> Self {
+        Self { view: View::default(), layout }
+    }
+
+    pub fn with_view(mut self, view: View) -> Self {
+        self.view = view;
+        self
+    }
+
+    /// Forward, right and up of `face` in the camera basis.
+    fn face(face: usize) -> [Vector3; 3] {
+        let x = Vector3::new(1., 0., 0.);
+        let y = Vector3::new(0., 1., 0.);
+        let z = Vector3::new(0., 0., 1.);
+
+        match face {
+            0 => [x, -z, y],
+            1 => [-x, z, y],
+            2 => [y, x, -z],
+            3 => [-y, x, z],
+            4 => [z, x, y],
+            _ => [-z, -x, y]
+        }
+    }
+}
+
+impl CameraModel for CubeMap {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        let (face, u, v) = self.layout.split(u, v, 6);
+        let [forward, right, up] = Self::face(face);
+
+        // image plane at unit distance, tan(45) = 1 at the face edges
+        let local = forward + right * (2. * u - 1.) + up * (1. - 2. * v);
+        Some(Ray::new(&self.view.origin, &self.view.direction(local.x, local.y, local.z)))
+    }
+
+    fn view(&self) -> View {
+        self.view
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("CubeMap");
+        self.view.fingerprint(h);
+        self.layout.fingerprint(h);
+    }
+}
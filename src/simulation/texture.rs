@@ -0,0 +1,318 @@
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+
+use crate::math::color::{self, ColorSpace};
+use crate::math::noise::hash::{Gnoise, Perlin, Vnoise};
+use crate::math::vector::{Color, Point2D, Point3D};
+use crate::simulation::checkpoint::SceneHasher;
+
+pub trait Texture: Send + Sync {
+    fn value(&self, uv: Point2D, p: Point3D) -> Color;
+
+    /// Single channel lookup, used for height / roughness style inputs.
+    fn scalar(&self, uv: Point2D, p: Point3D) -> f32 {
+        let c = self.value(uv, p);
+        (c.x + c.y + c.z) / 3.
+    }
+
+    /// Hashes what affects the rendered image, see `SceneHasher`.
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str(std::any::type_name::<Self>());
+    }
+}
+
+pub struct SolidColor {
+    color: Color
+}
+
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum NoiseKind {
+    Value,
+    Gradient,
+    Perlin
+}
+
+/// Procedural texture backed by `math::noise`, evaluated in object space.
+pub struct NoiseTexture {
+    kind: NoiseKind,
+    scale: f32,
+    octaves: i32
+}
+
+/// Bitmap texture, texels are decoded to the working space once when the texture is created.
+pub struct ImageTexture {
+    width: i32,
+    height: i32,
+    texels: Vec<Color>
+}
+
+#[allow(dead_code)]
+impl SolidColor {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _uv: Point2D, _p: Point3D) -> Color {
+        self.color
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("SolidColor");
+        h.write_vector(self.color);
+    }
+}
+
+#[allow(dead_code)]
+impl NoiseTexture {
+    pub fn new(kind: NoiseKind, scale: f32) -> Self {
+        Self { kind, scale, octaves: 1 }
+    }
+
+    /// Sums `octaves` layers of noise, each at twice the frequency and half the amplitude.
+    pub fn with_octaves(mut self, octaves: i32) -> Self {
+        self.octaves = octaves.max(1);
+        self
+    }
+
+    fn sample(&self, p: Point3D) -> f32 {
+        match self.kind {
+            NoiseKind::Value => Vnoise::rand31(p),
+            NoiseKind::Gradient => Gnoise::rand31(p),
+            NoiseKind::Perlin => Perlin::rand31(p)
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, uv: Point2D, p: Point3D) -> Color {
+        let n = self.scalar(uv, p);
+        Color::new(n, n, n)
+    }
+
+    fn scalar(&self, _uv: Point2D, p: Point3D) -> f32 {
+        let mut sum = 0.;
+        let mut weight = 0.;
+        let mut amplitude = 1.;
+        let mut q = p * self.scale;
+
+        for _ in 0..self.octaves {
+            sum += self.sample(q) * amplitude;
+            weight += amplitude;
+            amplitude *= 0.5;
+            q *= 2.;
+        }
+
+        sum / weight
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("NoiseTexture");
+        h.write_u8(self.kind as u8);
+        h.write_f32(self.scale);
+        h.write_i32(self.octaves);
+    }
+}
+
+#[allow(dead_code)]
+impl ImageTexture {
+    /// `texels` are in `color_space` and converted to `working_space`, the space the renderer shades
+    /// in. `ColorSpace::Raw` data is kept as is.
+    pub fn new(width: i32, height: i32, texels: Vec<Color>, color_space: ColorSpace, working_space: ColorSpace) -> Self {
+        if color_space == ColorSpace::Raw {
+            return Self { width, height, texels };
+        }
+
+        let (texels, color_space) = match color_space {
+            ColorSpace::Srgb => (
+                texels.iter().map(|c| Color::new(color::srgb_decode(c.x), color::srgb_decode(c.y), color::srgb_decode(c.z))).collect(),
+                ColorSpace::LinearSrgb
+            ),
+            s => (texels, s)
+        };
+
+        let working_space = working_space.linear();
+        let texels = texels.iter().map(|&c| ColorSpace::convert(c, color_space, working_space)).collect();
+        Self { width, height, texels }
+    }
+
+    /// Loads a color image, assumed to be display sRGB encoded.
+    pub fn load(file_name: &str, working_space: ColorSpace) -> io::Result<Self> {
+        Self::load_tagged(file_name, ColorSpace::Srgb, working_space)
+    }
+
+    /// Loads a binary (P6) or ascii (P3) PPM file whose values are in `color_space`.
+    /// Use `ColorSpace::Raw` for non-color data such as normal or height maps.
+    pub fn load_tagged(file_name: &str, color_space: ColorSpace, working_space: ColorSpace) -> io::Result<Self> {
+        let bytes = fs::read(file_name)?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file_name, msg));
+
+        // header: magic, width, height, max value, separated by whitespace and comments
+        let mut fields: Vec<String> = Vec::new();
+        let mut pos = 0;
+        while fields.len() < 4 && pos < bytes.len() {
+            let c = bytes[pos];
+            if c == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                pos += 1;
+            } else {
+                let start = pos;
+                while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                fields.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+            }
+        }
+        // exactly one whitespace separates the header from binary data
+        pos += 1;
+
+        if fields.len() < 4 {
+            return Err(invalid("truncated header"));
+        }
+
+        let dimension = |field: &str, msg: &str| match field.parse::<usize>() {
+            Ok(v) if v > 0 && v <= i32::MAX as usize => Ok(v),
+            _ => Err(invalid(msg))
+        };
+        let width = dimension(&fields[1], "bad width")?;
+        let height = dimension(&fields[2], "bad height")?;
+        let max_value = match fields[3].parse::<u16>() {
+            Ok(v) if v > 0 => v,
+            _ => return Err(invalid("bad max value"))
+        };
+        let size = width
+            .checked_mul(height)
+            .and_then(|count| count.checked_mul(3))
+            .filter(|&size| size <= i32::MAX as usize)
+            .ok_or_else(|| invalid("image too large"))?;
+
+        let values: Vec<f32> = match fields[0].as_str() {
+            "P6" => {
+                // samples above 255 take two bytes, most significant first
+                let sample_bytes = if max_value > 255 { 2 } else { 1 };
+                let end = size
+                    .checked_mul(sample_bytes)
+                    .and_then(|len| pos.checked_add(len))
+                    .ok_or_else(|| invalid("image too large"))?;
+                let data = bytes.get(pos..end).ok_or_else(|| invalid("truncated data"))?;
+                if sample_bytes == 2 {
+                    data.chunks(2).map(|b| u16::from_be_bytes([b[0], b[1]]) as f32).collect()
+                } else {
+                    data.iter().map(|&b| b as f32).collect()
+                }
+            }
+            "P3" => {
+                let text = String::from_utf8_lossy(bytes.get(pos..).unwrap_or(&[]));
+                let values: Vec<f32> = text.split_ascii_whitespace().take(size).filter_map(|s| s.parse().ok()).collect();
+                if values.len() < size {
+                    return Err(invalid("truncated data"));
+                }
+                values
+            }
+            _ => return Err(invalid("unsupported format"))
+        };
+
+        let texels = values
+            .chunks(3)
+            .map(|c| Color::new(c[0], c[1], c[2]) / max_value as f32)
+            .collect();
+
+        Ok(Self::new(width as i32, height as i32, texels, color_space, working_space))
+    }
+
+    pub fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    /// Stored value of texel (x, y), wrapping around, y = 0 is the top row.
+    pub fn texel(&self, x: i32, y: i32) -> Color {
+        let x = x.rem_euclid(self.width);
+        let y = y.rem_euclid(self.height);
+        self.texels[(x + y * self.width) as usize]
+    }
+}
+
+impl Texture for ImageTexture {
+    /// Bilinear lookup with repeat wrapping, v = 0 is the bottom row.
+    fn value(&self, uv: Point2D, _p: Point3D) -> Color {
+        if self.texels.is_empty() {
+            return Color::zero();
+        }
+
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (1. - uv.y) * self.height as f32 - 0.5;
+
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let a = Color::lerp(self.texel(x0, y0), self.texel(x0 + 1, y0), fx);
+        let b = Color::lerp(self.texel(x0, y0 + 1), self.texel(x0 + 1, y0 + 1), fx);
+
+        Color::lerp(a, b, fy)
+    }
+
+    fn fingerprint(&self, h: &mut SceneHasher) {
+        h.write_str("ImageTexture");
+        h.write_i32(self.width);
+        h.write_i32(self.height);
+        for &texel in &self.texels {
+            h.write_vector(texel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_bytes(name: &str, bytes: &[u8]) -> io::Result<ImageTexture> {
+        let path = std::env::temp_dir().join(format!("ray_tracing_{}_{}.ppm", name, std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        let result = ImageTexture::load_tagged(&path.to_string_lossy(), ColorSpace::Raw, ColorSpace::LinearSrgb);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn load_reads_binary_and_ascii() {
+        let binary = load_bytes("binary", b"P6\n# comment\n2 1\n255\n\xff\x00\x00\x00\x00\xff").unwrap();
+        let wide = load_bytes("wide", b"P6\n2 1\n65535\n\xff\xff\x00\x00\x00\x00\x00\x00\x00\x00\xff\xff").unwrap();
+        let ascii = load_bytes("ascii", b"P3 2 1 255\n255 0 0 0 0 255\n").unwrap();
+
+        for image in [binary, wide, ascii] {
+            assert_eq!((image.width, image.height), (2, 1));
+            let (a, b) = (image.texel(0, 0), image.texel(1, 0));
+            assert_eq!([a.x, a.y, a.z, b.x, b.y, b.z], [1., 0., 0., 0., 0., 1.]);
+        }
+    }
+
+    #[test]
+    fn load_rejects_malformed_headers() {
+        let headers: [&[u8]; 9] = [
+            b"P6\n2 1\n",
+            b"P6\n-2 1\n255\n\x00\x00\x00\x00\x00\x00",
+            b"P6\n0 1\n255\n",
+            b"P6\n2 x\n255\n\x00\x00\x00\x00\x00\x00",
+            b"P6\n2 1\n0\n\x00\x00\x00\x00\x00\x00",
+            b"P6\n4294967296 4294967296\n255\n\x00",
+            b"P6\n2 2\n255\n\x00\x00\x00",
+            b"P6\n2 1\n65535\n\x00\x00\x00\x00\x00\x00",
+            b"P5\n2 1\n255\n\x00\x00"
+        ];
+
+        for (i, header) in headers.iter().enumerate() {
+            let result = load_bytes(&format!("malformed_{}", i), header);
+            assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData), "header {}", i);
+        }
+    }
+}